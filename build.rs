@@ -1,9 +1,18 @@
+#[cfg(feature = "libsensors")]
 extern crate bindgen;
 
+#[cfg(feature = "libsensors")]
 use std::env;
+#[cfg(feature = "libsensors")]
 use std::path::PathBuf;
 
 fn main() {
+    #[cfg(feature = "libsensors")]
+    generate_libsensors_bindings();
+}
+
+#[cfg(feature = "libsensors")]
+fn generate_libsensors_bindings() {
     // Tell cargo to tell rustc to link the system sensors
     // shared library.
     println!("cargo:rustc-link-lib=sensors");
@@ -0,0 +1,395 @@
+use crate::config::Config;
+use crate::helpers;
+use crate::metrics::MetricSink;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::panic;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub trait Collector: Send {
+    /// Name of the collector, matches the CLI name of the exporter
+    fn name(&self) -> &'static str;
+
+    /// Called once before the first scrape
+    fn init(&mut self) -> Result<(), CollectorError> {
+        Ok(())
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError>;
+}
+
+#[derive(Debug)]
+pub enum CollectorError {
+    Io(io::Error),
+    /// Unexpected output from a file, command or daemon
+    Parse(String),
+    /// Device or helper process is not available
+    Unavailable(String),
+    Panic(String),
+    /// Still busy with a previous scrape
+    Busy,
+    /// Did not finish before the scrape deadline
+    Timeout,
+}
+
+impl fmt::Display for CollectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollectorError::Io(e) => write!(f, "I/O error: {e}"),
+            CollectorError::Parse(msg) => write!(f, "parse error: {msg}"),
+            CollectorError::Unavailable(msg) => write!(f, "unavailable: {msg}"),
+            CollectorError::Panic(msg) => write!(f, "panicked: {msg}"),
+            CollectorError::Busy => write!(f, "previous scrape is still running"),
+            CollectorError::Timeout => write!(f, "did not finish before the deadline"),
+        }
+    }
+}
+
+impl From<io::Error> for CollectorError {
+    fn from(e: io::Error) -> Self {
+        CollectorError::Io(e)
+    }
+}
+
+impl From<ParseIntError> for CollectorError {
+    fn from(e: ParseIntError) -> Self {
+        CollectorError::Parse(e.to_string())
+    }
+}
+
+impl From<ParseFloatError> for CollectorError {
+    fn from(e: ParseFloatError) -> Self {
+        CollectorError::Parse(e.to_string())
+    }
+}
+
+/// Runs `f` and turns a panic into an error so that a broken collector
+/// cannot take down the thread serving the scrape
+fn catch_panic<F>(f: F) -> Result<(), CollectorError>
+where
+    F: FnOnce() -> Result<(), CollectorError>,
+{
+    panic::catch_unwind(panic::AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(CollectorError::Panic(panic_message(payload.as_ref()))))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[clap(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+/// Only the collectors which were compiled in are offered on the command line
+pub enum Exporter {
+    #[cfg(feature = "hddtemp")]
+    Hddtemp,
+    #[cfg(feature = "libsensors")]
+    LmSensors,
+    Hwmon,
+    NetClass,
+    #[cfg(feature = "procfs")]
+    ProcMeminfo,
+    #[cfg(feature = "procfs")]
+    ProcNetdev,
+    #[cfg(feature = "procfs")]
+    ProcStat,
+    #[cfg(feature = "nvidia")]
+    Nvidia,
+    #[cfg(feature = "hidapi")]
+    NzxtAio, // No longer needed because these sensors are exposed in lm_sensors
+    #[cfg(feature = "radeontop")]
+    AmdRadeontop,
+}
+
+impl Exporter {
+    pub fn collector(self, config: &Config) -> Box<dyn Collector> {
+        match self {
+            #[cfg(feature = "hddtemp")]
+            Exporter::Hddtemp => Box::new(helpers::hddtemp::Hddtemp::new(&config.hddtemp)),
+            #[cfg(feature = "libsensors")]
+            Exporter::LmSensors => {
+                Box::new(helpers::lm_sensors::get_lm_sensors(&config.lm_sensors))
+            }
+            Exporter::Hwmon => Box::new(helpers::hwmon::Hwmon::new(&config.paths.sysfs())),
+            Exporter::NetClass => Box::new(helpers::net_class::NetClass::new(
+                &config.paths.sysfs(),
+                &config.net_class,
+            )),
+            #[cfg(feature = "procfs")]
+            Exporter::ProcMeminfo => Box::new(helpers::proc_meminfo::ProcMeminfo::new(
+                &config.paths.procfs(),
+                config.compat,
+            )),
+            #[cfg(feature = "procfs")]
+            Exporter::ProcNetdev => Box::new(helpers::proc_netdev::ProcNetdev::new(
+                &config.paths.procfs(),
+                config.compat,
+                &config.proc_netdev,
+            )),
+            #[cfg(feature = "procfs")]
+            Exporter::ProcStat => Box::new(helpers::proc_stat::ProcStat::new(
+                &config.paths.procfs(),
+                config.compat,
+                &config.proc_stat,
+            )),
+            #[cfg(feature = "nvidia")]
+            Exporter::Nvidia => Box::new(helpers::nvidia::Nvidia),
+            #[cfg(feature = "hidapi")]
+            Exporter::NzxtAio => Box::new(helpers::nzxt_aio::NzxtAio::new(&config.nzxt_aio)),
+            #[cfg(feature = "radeontop")]
+            Exporter::AmdRadeontop => Box::new(helpers::amd_radeontop::AmdRadeontop::new(
+                &config.amd_radeontop,
+            )),
+        }
+    }
+}
+
+struct Entry {
+    exporter: Exporter,
+    name: &'static str,
+    collector: Arc<Mutex<Box<dyn Collector>>>,
+}
+
+impl Entry {
+    fn new(exporter: Exporter, config: &Config) -> Entry {
+        let collector = exporter.collector(config);
+        Entry {
+            exporter,
+            name: collector.name(),
+            collector: Arc::new(Mutex::new(collector)),
+        }
+    }
+
+    fn init(&self) {
+        println!("Initializing collector {}", self.name);
+        let mut collector = self.collector.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = catch_panic(|| collector.init()) {
+            println!("Collector {} failed to initialize: {e}", self.name);
+        }
+    }
+}
+
+/// Result of a single collector during a scrape
+pub struct Outcome {
+    pub name: &'static str,
+    pub duration: Duration,
+    pub result: Result<(), CollectorError>,
+}
+
+/// Metrics of the collectors which finished in time including the per-collector
+/// self-metrics, plus the outcome of every collector
+pub struct Scrape {
+    pub sink: MetricSink,
+    pub outcomes: Vec<Outcome>,
+}
+
+/// Set of enabled collectors which are scraped together
+pub struct Registry {
+    entries: Vec<Entry>,
+    config: Config,
+}
+
+fn enabled_exporters(config: &Config) -> Vec<Exporter> {
+    let mut exporters = config.collectors.clone();
+    exporters.sort();
+    exporters.dedup();
+    exporters
+}
+
+impl Registry {
+    pub fn new(config: &Config) -> Registry {
+        Registry {
+            entries: enabled_exporters(config)
+                .into_iter()
+                .map(|exporter| Entry::new(exporter, config))
+                .collect(),
+            config: config.clone(),
+        }
+    }
+
+    /// Builds the registry for a new config. Collectors whose options did not
+    /// change are shared with this registry and keep their state, the others
+    /// are created and initialized. Collectors which are not carried over are
+    /// dropped together with the last reference to this registry.
+    pub fn reload(&self, config: &Config) -> Registry {
+        let entries = enabled_exporters(config)
+            .into_iter()
+            .map(|exporter| {
+                let current = self
+                    .entries
+                    .iter()
+                    .find(|entry| entry.exporter == exporter)
+                    .filter(|_| !self.config.changed_for(config, exporter));
+                match current {
+                    Some(entry) => Entry {
+                        exporter,
+                        name: entry.name,
+                        collector: Arc::clone(&entry.collector),
+                    },
+                    None => {
+                        let entry = Entry::new(exporter, config);
+                        entry.init();
+                        entry
+                    }
+                }
+            })
+            .collect();
+
+        Registry {
+            entries,
+            config: config.clone(),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|entry| entry.name).collect()
+    }
+
+    /// Initializes all collectors. A collector which fails to initialize stays
+    /// registered and reports its failures on every scrape.
+    pub fn init(&mut self) {
+        for entry in self.entries.iter() {
+            entry.init();
+        }
+    }
+
+    /// Runs all collectors in parallel and waits for them up to `timeout`.
+    /// A collector which is still busy with a previous scrape is skipped.
+    pub fn collect(&self, timeout: Duration) -> Scrape {
+        let start = Instant::now();
+        let deadline = start + timeout;
+        let (tx, rx) = mpsc::channel();
+
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let tx = tx.clone();
+            let collector = Arc::clone(&entry.collector);
+            let name = entry.name;
+            thread::spawn(move || {
+                let start = Instant::now();
+                let mut sink = MetricSink::default();
+                let result = match collector.try_lock() {
+                    Ok(mut collector) => catch_panic(|| collector.collect(&mut sink)),
+                    Err(TryLockError::Poisoned(e)) => {
+                        let mut collector = e.into_inner();
+                        catch_panic(|| collector.collect(&mut sink))
+                    }
+                    Err(TryLockError::WouldBlock) => Err(CollectorError::Busy),
+                };
+                let outcome = Outcome {
+                    name,
+                    duration: start.elapsed(),
+                    result,
+                };
+                let _ = tx.send((idx, sink, outcome));
+            });
+        }
+        drop(tx);
+
+        let mut results: Vec<Option<(MetricSink, Outcome)>> =
+            self.entries.iter().map(|_| None).collect();
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match rx.recv_timeout(remaining) {
+                Ok((idx, sink, outcome)) => results[idx] = Some((sink, outcome)),
+                // Timeout, or all collectors have finished
+                Err(_) => break,
+            }
+        }
+
+        let mut scrape = Scrape {
+            sink: MetricSink::default(),
+            outcomes: Vec::new(),
+        };
+        for (entry, result) in self.entries.iter().zip(results) {
+            let outcome = match result {
+                Some((sink, outcome)) => {
+                    // Partial results of a failed collector are dropped
+                    if outcome.result.is_ok() {
+                        scrape.sink.merge(sink);
+                    }
+                    outcome
+                }
+                None => Outcome {
+                    name: entry.name,
+                    duration: start.elapsed(),
+                    result: Err(CollectorError::Timeout),
+                },
+            };
+
+            let labels = [("collector", entry.name)];
+            scrape.sink.gauge_with_labels(
+                "node_scrape_collector_duration_seconds",
+                "Duration of a collector scrape",
+                &labels,
+                outcome.duration.as_secs_f64(),
+            );
+            scrape.sink.gauge_with_labels(
+                "node_scrape_collector_success",
+                "Whether a collector succeeded",
+                &labels,
+                if outcome.result.is_ok() { 1.0 } else { 0.0 },
+            );
+
+            scrape.outcomes.push(outcome);
+        }
+        scrape
+    }
+}
+
+// The filter test needs proc_netdev next to net_class
+#[cfg(all(test, feature = "procfs"))]
+mod tests {
+    use super::*;
+    use crate::config::DeviceFilterConfig;
+    use crate::testing::{self, fixtures};
+
+    fn devices(exporter: Exporter, config: &Config, family: &str) -> Vec<String> {
+        testing::collect(exporter.collector(config).as_mut())
+            .unwrap()
+            .family(family)
+            .unwrap()
+            .samples
+            .iter()
+            .map(|sample| sample.labels[0].1.clone())
+            .collect()
+    }
+
+    #[test]
+    fn device_filters() {
+        let fixtures = fixtures();
+        let mut config = Config::default();
+        config.paths.procfs = Some(fixtures.join("proc"));
+        config.paths.sysfs = Some(fixtures.join("sys"));
+        config.proc_netdev = DeviceFilterConfig {
+            device_exclude: Some("^(lo|docker)".to_string()),
+            ..Default::default()
+        };
+        config.net_class = DeviceFilterConfig {
+            device_include: Some("^enp".to_string()),
+            ..Default::default()
+        };
+
+        // Each collector only applies its own filter
+        assert_eq!(
+            devices(Exporter::ProcNetdev, &config, "procnetdev_rx_bytes_total"),
+            ["enp5s0", "wlan0"]
+        );
+        assert_eq!(
+            devices(Exporter::NetClass, &config, "node_network_up"),
+            ["enp5s0"]
+        );
+    }
+}
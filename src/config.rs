@@ -0,0 +1,418 @@
+// Collector configuration read from --config.file, every value has a default
+// matching the machine this exporter was originally written for
+use crate::collector::Exporter;
+use clap::ValueEnum;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Enabled collectors
+    pub collectors: Vec<Exporter>,
+    pub compat: Compat,
+    pub lm_sensors: LmSensorsConfig,
+    pub hddtemp: HddtempConfig,
+    pub amd_radeontop: AmdRadeontopConfig,
+    pub nzxt_aio: NzxtAioConfig,
+    pub net_class: DeviceFilterConfig,
+    pub proc_netdev: DeviceFilterConfig,
+    pub proc_stat: ProcStatConfig,
+    pub paths: PathsConfig,
+}
+
+/// Metric naming of the procfs collectors
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[clap(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Compat {
+    /// procmeminfo_*, procnetdev_* and procstat_* names
+    #[default]
+    None,
+    /// The names of the upstream node_exporter, e.g. node_memory_MemTotal_bytes,
+    /// so that community dashboards work as-is
+    NodeExporter,
+}
+
+/// Values from CLI flags which take precedence over the config file
+pub struct Overrides {
+    pub collectors: Vec<Exporter>,
+    pub compat: Option<Compat>,
+    pub hddtemp_address: Option<String>,
+    pub radeontop_interval: Option<u32>,
+    pub netdev_device_include: Option<String>,
+    pub netdev_device_exclude: Option<String>,
+    pub netclass_device_include: Option<String>,
+    pub netclass_device_exclude: Option<String>,
+    pub proc_stat_legacy_names: bool,
+    pub procfs: Option<PathBuf>,
+    pub sysfs: Option<PathBuf>,
+    pub rootfs: Option<PathBuf>,
+}
+
+/// Mount points of the host filesystems, for running in a container or
+/// against captured fixture trees
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Defaults to `proc` below `rootfs`
+    pub procfs: Option<PathBuf>,
+    /// Defaults to `sys` below `rootfs`
+    pub sysfs: Option<PathBuf>,
+    pub rootfs: PathBuf,
+}
+
+impl PathsConfig {
+    #[cfg(feature = "procfs")]
+    pub fn procfs(&self) -> PathBuf {
+        self.procfs
+            .clone()
+            .unwrap_or_else(|| self.rootfs.join("proc"))
+    }
+
+    pub fn sysfs(&self) -> PathBuf {
+        self.sysfs
+            .clone()
+            .unwrap_or_else(|| self.rootfs.join("sys"))
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LmSensorsConfig {
+    /// Metric names by `chip prefix/subfeature name`
+    pub relevant_keys: HashMap<String, String>,
+    /// Also export every readable sensor as lm_sensors_temp_celsius{chip,sensor,label}
+    /// and similar families
+    pub export_all: bool,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HddtempConfig {
+    /// Address of the hddtemp daemon
+    pub address: String,
+    /// Regex matched against the daemon output, with one capture group per drive
+    pub pattern: String,
+    /// Metric names for the capture groups of `pattern`, in order
+    pub metrics: Vec<String>,
+    /// Seconds to wait for the daemon when connecting and reading
+    pub timeout: f64,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmdRadeontopConfig {
+    /// Seconds between the samples dumped by radeontop
+    pub interval: u32,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NzxtAioConfig {
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+/// Network devices exported by proc_netdev or net_class, at most one regex can be set
+#[derive(Deserialize, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceFilterConfig {
+    /// Regex of the only devices to export, e.g. ^(enp|wlan)
+    pub device_include: Option<String>,
+    /// Regex of devices to leave out, e.g. ^(veth|docker|br-)
+    pub device_exclude: Option<String>,
+}
+
+impl DeviceFilterConfig {
+    fn validate(&self, section: &str) -> Result<(), String> {
+        if self.device_include.is_some() && self.device_exclude.is_some() {
+            return Err(format!(
+                "{section} device_include and device_exclude are mutually exclusive"
+            ));
+        }
+        for pattern in [&self.device_include, &self.device_exclude]
+            .into_iter()
+            .flatten()
+        {
+            Regex::new(pattern).map_err(|e| format!("invalid {section} device pattern: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcStatConfig {
+    /// Export the CPU times as procstat_cpu3_user_hz and similar instead of
+    /// node_cpu_seconds_total{cpu,mode}
+    pub legacy_names: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            collectors: vec![
+                #[cfg(feature = "hddtemp")]
+                Exporter::Hddtemp,
+                #[cfg(feature = "libsensors")]
+                Exporter::LmSensors,
+                #[cfg(not(feature = "libsensors"))]
+                Exporter::Hwmon,
+                Exporter::NetClass,
+                #[cfg(feature = "procfs")]
+                Exporter::ProcMeminfo,
+                #[cfg(feature = "procfs")]
+                Exporter::ProcNetdev,
+                #[cfg(feature = "procfs")]
+                Exporter::ProcStat,
+                #[cfg(feature = "radeontop")]
+                Exporter::AmdRadeontop,
+            ],
+            compat: Compat::default(),
+            lm_sensors: LmSensorsConfig::default(),
+            hddtemp: HddtempConfig::default(),
+            amd_radeontop: AmdRadeontopConfig::default(),
+            nzxt_aio: NzxtAioConfig::default(),
+            net_class: DeviceFilterConfig::default(),
+            proc_netdev: DeviceFilterConfig::default(),
+            proc_stat: ProcStatConfig::default(),
+            paths: PathsConfig::default(),
+        }
+    }
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            procfs: None,
+            sysfs: None,
+            rootfs: PathBuf::from("/"),
+        }
+    }
+}
+
+impl Default for LmSensorsConfig {
+    fn default() -> Self {
+        let relevant_keys = [
+            ("k10temp/temp1_input", "lm_sensors_tctl"),
+            ("k10temp/temp2_input", "lm_sensors_tdie"),
+            ("asusec/temp2_input", "lm_sensors_cpu_temp"),
+            ("asusec/temp3_input", "lm_sensors_mb_temp"),
+            ("asusec/temp1_input", "lm_sensors_chipset_temp"),
+            // NZXT kraken2 data
+            ("kraken2/temp1_input", "aio_liquid_temp"),
+            ("kraken2/fan1_input", "aio_fan_speed"),
+            ("kraken2/fan2_input", "aio_pump_speed"),
+            // amdgpu sensors
+            ("amdgpu/in0_input", "amdgpu_vddgfx_voltage"),
+            ("amdgpu/fan1_input", "amdgpu_fan1_speed"),
+            ("amdgpu/temp2_input", "amdgpu_junction_temp"),
+            ("amdgpu/temp3_input", "amdgpu_mem_temp"),
+            ("amdgpu/power1_average", "amdgpu_slowppt_wattage"),
+            // wireless adapater
+            ("iwlwifi_1/temp1_input", "wireless_temp"),
+            // logitech mouse voltage
+            ("hidpp_battery_0/in0_input", "mouse_battery_voltage"),
+        ];
+
+        LmSensorsConfig {
+            relevant_keys: relevant_keys
+                .into_iter()
+                .map(|(key, metric)| (key.to_string(), metric.to_string()))
+                .collect(),
+            export_all: false,
+        }
+    }
+}
+
+impl Default for HddtempConfig {
+    fn default() -> Self {
+        HddtempConfig {
+            address: "127.0.0.1:7634".to_string(),
+            pattern: r"\|CT1000MX500SSD4\|([0-9.]+)\|C\|.+\|Samsung SSD 860 EVO M.2\|([0-9.]+)\|C\|.+\|WDC WD20EFZX-68AWUN0\|([0-9.]+)\|C\|.+\|WDC WD20EFZX-68AWUN0\|([0-9.]+)\|C\|.*$".to_string(),
+            metrics: vec![
+                "hddtemp_crucial_mx500_temp".to_string(),
+                "hddtemp_samsung_860_evo_temp".to_string(),
+                "hddtemp_wd_red_plus_1_temp".to_string(),
+                "hddtemp_wd_red_plus_2_temp".to_string(),
+            ],
+            timeout: 5.0,
+        }
+    }
+}
+
+impl Default for AmdRadeontopConfig {
+    fn default() -> Self {
+        AmdRadeontopConfig { interval: 15 }
+    }
+}
+
+impl Default for NzxtAioConfig {
+    fn default() -> Self {
+        // Kraken X52
+        NzxtAioConfig {
+            vendor_id: 0x1e71,
+            product_id: 0x170e,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file if there is one and applies the overrides
+    pub fn load(path: Option<&Path>, overrides: &Overrides) -> Result<Config, String> {
+        let mut config = match path {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+                toml::from_str(&content)
+                    .map_err(|e| format!("could not parse {}: {e}", path.display()))?
+            }
+            None => Config::default(),
+        };
+
+        if !overrides.collectors.is_empty() {
+            config.collectors = overrides.collectors.clone();
+        }
+        if let Some(compat) = overrides.compat {
+            config.compat = compat;
+        }
+        if let Some(address) = &overrides.hddtemp_address {
+            config.hddtemp.address = address.clone();
+        }
+        if let Some(interval) = overrides.radeontop_interval {
+            config.amd_radeontop.interval = interval;
+        }
+        if let Some(include) = &overrides.netdev_device_include {
+            config.proc_netdev.device_include = Some(include.clone());
+        }
+        if let Some(exclude) = &overrides.netdev_device_exclude {
+            config.proc_netdev.device_exclude = Some(exclude.clone());
+        }
+        if let Some(include) = &overrides.netclass_device_include {
+            config.net_class.device_include = Some(include.clone());
+        }
+        if let Some(exclude) = &overrides.netclass_device_exclude {
+            config.net_class.device_exclude = Some(exclude.clone());
+        }
+        if overrides.proc_stat_legacy_names {
+            config.proc_stat.legacy_names = true;
+        }
+        if let Some(procfs) = &overrides.procfs {
+            config.paths.procfs = Some(procfs.clone());
+        }
+        if let Some(sysfs) = &overrides.sysfs {
+            config.paths.sysfs = Some(sysfs.clone());
+        }
+        if let Some(rootfs) = &overrides.rootfs {
+            config.paths.rootfs = rootfs.clone();
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Whether the options used by the `exporter` collector differ between the configs
+    pub fn changed_for(&self, other: &Config, exporter: Exporter) -> bool {
+        match exporter {
+            #[cfg(feature = "hddtemp")]
+            Exporter::Hddtemp => self.hddtemp != other.hddtemp,
+            #[cfg(feature = "libsensors")]
+            Exporter::LmSensors => self.lm_sensors != other.lm_sensors,
+            #[cfg(feature = "hidapi")]
+            Exporter::NzxtAio => self.nzxt_aio != other.nzxt_aio,
+            #[cfg(feature = "radeontop")]
+            Exporter::AmdRadeontop => self.amd_radeontop != other.amd_radeontop,
+            Exporter::Hwmon => self.paths.sysfs() != other.paths.sysfs(),
+            Exporter::NetClass => {
+                self.paths.sysfs() != other.paths.sysfs() || self.net_class != other.net_class
+            }
+            #[cfg(feature = "procfs")]
+            Exporter::ProcMeminfo => {
+                self.paths.procfs() != other.paths.procfs() || self.compat != other.compat
+            }
+            #[cfg(feature = "procfs")]
+            Exporter::ProcNetdev => {
+                self.paths.procfs() != other.paths.procfs()
+                    || self.compat != other.compat
+                    || self.proc_netdev != other.proc_netdev
+            }
+            #[cfg(feature = "procfs")]
+            Exporter::ProcStat => {
+                self.paths.procfs() != other.paths.procfs()
+                    || self.compat != other.compat
+                    || self.proc_stat != other.proc_stat
+            }
+            // Only hwmon and net_class are left without the optional collectors
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        // A single invalid name would make the whole exposition unparseable
+        let names = self
+            .lm_sensors
+            .relevant_keys
+            .values()
+            .chain(self.hddtemp.metrics.iter());
+        for name in names {
+            if !is_valid_metric_name(name) {
+                return Err(format!("invalid metric name {name:?}"));
+            }
+        }
+        let pattern = Regex::new(&self.hddtemp.pattern)
+            .map_err(|e| format!("invalid hddtemp pattern: {e}"))?;
+        // The first group is the whole match
+        let groups = pattern.captures_len() - 1;
+        if groups != self.hddtemp.metrics.len() {
+            return Err(format!(
+                "hddtemp pattern has {groups} capture groups but {} metrics are configured",
+                self.hddtemp.metrics.len()
+            ));
+        }
+        self.proc_netdev.validate("proc_netdev")?;
+        self.net_class.validate("net_class")?;
+        // Duration::from_secs_f64 in Hddtemp::new panics on values out of range
+        if self.hddtemp.timeout <= 0.0 || Duration::try_from_secs_f64(self.hddtemp.timeout).is_err()
+        {
+            return Err(format!(
+                "hddtemp timeout {} is not a positive number of seconds in range",
+                self.hddtemp.timeout
+            ));
+        }
+        if self.amd_radeontop.interval == 0 {
+            return Err("amd_radeontop interval must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Matches [a-zA-Z_:][a-zA-Z0-9_:]*
+fn is_valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first.is_ascii_alphabetic() || first == '_' || first == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hddtemp_timeout() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+        for timeout in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e30] {
+            config.hddtemp.timeout = timeout;
+            assert!(config.validate().is_err(), "{timeout}");
+        }
+    }
+}
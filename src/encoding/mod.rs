@@ -0,0 +1,170 @@
+pub mod openmetrics;
+pub mod protobuf;
+pub mod text;
+
+use crate::metrics::MetricFamily;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+    Protobuf,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => text::CONTENT_TYPE,
+            Format::OpenMetrics => openmetrics::CONTENT_TYPE,
+            Format::Protobuf => protobuf::CONTENT_TYPE,
+        }
+    }
+
+    pub fn encode(self, families: &[MetricFamily]) -> Vec<u8> {
+        match self {
+            Format::Text => text::encode(families).into_bytes(),
+            Format::OpenMetrics => openmetrics::encode(families).into_bytes(),
+            Format::Protobuf => protobuf::encode(families),
+        }
+    }
+}
+
+/// Picks the exposition format based on the `Accept` header of the scrape request.
+/// Media ranges are ordered by their `q` weight, earlier entries win ties.
+/// Falls back to the classic text format.
+pub fn negotiate(accept: Option<&str>) -> Format {
+    let Some(accept) = accept else {
+        return Format::Text;
+    };
+
+    let mut candidates: Vec<(f64, Format)> = Vec::new();
+    for media_range in accept.split(',') {
+        let mut params = media_range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+
+        let mut q = 1.0;
+        let mut version = None;
+        let mut proto = None;
+        let mut encoding = None;
+        for param in params {
+            if let Some((key, value)) = param.split_once('=') {
+                match key.trim() {
+                    "q" => q = value.trim().parse().unwrap_or(0.0),
+                    "version" => version = Some(value.trim()),
+                    "proto" => proto = Some(value.trim()),
+                    "encoding" => encoding = Some(value.trim()),
+                    _ => {}
+                }
+            }
+        }
+
+        let format = match media_type.as_str() {
+            "application/openmetrics-text"
+                if matches!(version, None | Some("1.0.0") | Some("0.0.1")) =>
+            {
+                Format::OpenMetrics
+            }
+            "application/vnd.google.protobuf"
+                if proto == Some("io.prometheus.client.MetricFamily")
+                    && encoding == Some("delimited") =>
+            {
+                Format::Protobuf
+            }
+            "text/plain" | "*/*" => Format::Text,
+            _ => continue,
+        };
+        if q > 0.0 {
+            candidates.push((q, format));
+        }
+    }
+
+    // sort_by is stable so header order is kept for equal weights
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    candidates
+        .first()
+        .map(|(_, format)| *format)
+        .unwrap_or(Format::Text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_by_default() {
+        assert_eq!(negotiate(None), Format::Text);
+        assert_eq!(negotiate(Some("")), Format::Text);
+        assert_eq!(negotiate(Some("*/*")), Format::Text);
+        assert_eq!(negotiate(Some("application/json")), Format::Text);
+    }
+
+    #[test]
+    fn openmetrics_versions() {
+        assert_eq!(
+            negotiate(Some("application/openmetrics-text")),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            negotiate(Some("application/openmetrics-text; version=1.0.0")),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            negotiate(Some("application/openmetrics-text;version=0.0.1")),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            negotiate(Some("application/openmetrics-text; version=2.0.0")),
+            Format::Text
+        );
+    }
+
+    #[test]
+    fn q_values() {
+        // What Prometheus sends by default
+        let prometheus = "application/openmetrics-text;version=1.0.0;q=0.5,\
+            application/openmetrics-text;version=0.0.1;q=0.4,\
+            text/plain;version=0.0.4;q=0.3,*/*;q=0.2";
+        assert_eq!(negotiate(Some(prometheus)), Format::OpenMetrics);
+        assert_eq!(
+            negotiate(Some("text/plain;q=0.9, application/openmetrics-text;q=0.5")),
+            Format::Text
+        );
+        // Equal weights keep the header order
+        assert_eq!(
+            negotiate(Some("application/openmetrics-text, text/plain")),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            negotiate(Some("application/openmetrics-text;q=0, text/plain;q=0.1")),
+            Format::Text
+        );
+        assert_eq!(
+            negotiate(Some("application/openmetrics-text;q=invalid")),
+            Format::Text
+        );
+    }
+
+    #[test]
+    fn protobuf_parameters() {
+        let delimited = "application/vnd.google.protobuf; \
+            proto=io.prometheus.client.MetricFamily; encoding=delimited";
+        assert_eq!(negotiate(Some(delimited)), Format::Protobuf);
+        assert_eq!(
+            negotiate(Some(&format!(
+                "{delimited};q=0.7,text/plain;version=0.0.4;q=0.3"
+            ))),
+            Format::Protobuf
+        );
+        // Only the delimited MetricFamily encoding is implemented
+        assert_eq!(
+            negotiate(Some(
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=text"
+            )),
+            Format::Text
+        );
+        assert_eq!(
+            negotiate(Some("application/vnd.google.protobuf;encoding=delimited")),
+            Format::Text
+        );
+    }
+}
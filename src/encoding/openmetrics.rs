@@ -0,0 +1,113 @@
+// OpenMetrics 1.0 text format
+// https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md
+use crate::encoding::text::{escape_label_value, format_value};
+use crate::metrics::{MetricFamily, MetricType};
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Units which are emitted as `# UNIT` when the family name ends with them
+const UNITS: &[&str] = &[
+    "seconds", "bytes", "celsius", "volts", "amperes", "joules", "watts", "hertz", "ratio",
+];
+
+pub fn encode(families: &[MetricFamily]) -> String {
+    let mut result = String::new();
+
+    for family in families {
+        if family.samples.is_empty() {
+            continue;
+        }
+
+        // Counter samples carry the `_total` suffix, the family name does not
+        let (family_name, sample_name) = match family.metric_type {
+            MetricType::Counter => {
+                let base = family.name.strip_suffix("_total").unwrap_or(&family.name);
+                (base.to_string(), format!("{base}_total"))
+            }
+            MetricType::Gauge => (family.name.clone(), family.name.clone()),
+        };
+
+        result.push_str(&format!(
+            "# TYPE {} {}\n",
+            family_name,
+            family.metric_type.as_str()
+        ));
+        if let Some(unit) = UNITS
+            .iter()
+            .find(|unit| family_name.ends_with(&format!("_{unit}")))
+        {
+            result.push_str(&format!("# UNIT {family_name} {unit}\n"));
+        }
+        result.push_str(&format!(
+            "# HELP {} {}\n",
+            family_name,
+            escape_label_value(&family.help)
+        ));
+
+        for sample in &family.samples {
+            result.push_str(&sample_name);
+            if !sample.labels.is_empty() {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(k, v)| format!("{k}=\"{}\"", escape_label_value(v)))
+                    .collect();
+                result.push_str(&format!("{{{}}}", labels.join(",")));
+            }
+            result.push_str(&format!(" {}\n", format_value(sample.value)));
+        }
+    }
+
+    result.push_str("# EOF\n");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricSink;
+
+    #[test]
+    fn suffixes_units_and_escaping() {
+        let mut sink = MetricSink::default();
+        sink.gauge_with_labels(
+            "node_hwmon_temp_celsius",
+            "Back\\slash and\nnewline, \"quotes\"",
+            &[("chip", "a\"b")],
+            42.5,
+        );
+        sink.counter(
+            "procstat_ctxt",
+            "Total number of context switches",
+            241585287.0,
+        );
+        sink.counter_with_labels(
+            "lm_sensors_energy_joules_total",
+            "Energy consumed in joules",
+            &[("chip", "amdgpu-pci-0b00")],
+            12.5,
+        );
+
+        assert_eq!(
+            encode(sink.families()),
+            r#"# TYPE node_hwmon_temp_celsius gauge
+# UNIT node_hwmon_temp_celsius celsius
+# HELP node_hwmon_temp_celsius Back\\slash and\nnewline, \"quotes\"
+node_hwmon_temp_celsius{chip="a\"b"} 42.5
+# TYPE procstat_ctxt counter
+# HELP procstat_ctxt Total number of context switches
+procstat_ctxt_total 241585287
+# TYPE lm_sensors_energy_joules counter
+# UNIT lm_sensors_energy_joules joules
+# HELP lm_sensors_energy_joules Energy consumed in joules
+lm_sensors_energy_joules_total{chip="amdgpu-pci-0b00"} 12.5
+# EOF
+"#
+        );
+    }
+
+    #[test]
+    fn empty_ends_with_eof() {
+        assert_eq!(encode(MetricSink::default().families()), "# EOF\n");
+    }
+}
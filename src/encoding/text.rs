@@ -0,0 +1,114 @@
+// Prometheus text exposition format 0.0.4
+// https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format
+use crate::metrics::MetricFamily;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub fn encode(families: &[MetricFamily]) -> String {
+    let mut result = String::new();
+
+    for family in families {
+        if family.samples.is_empty() {
+            continue;
+        }
+
+        result.push_str(&format!(
+            "# HELP {} {}\n",
+            family.name,
+            escape_help(&family.help)
+        ));
+        result.push_str(&format!(
+            "# TYPE {} {}\n",
+            family.name,
+            family.metric_type.as_str()
+        ));
+
+        for sample in &family.samples {
+            result.push_str(&family.name);
+            if !sample.labels.is_empty() {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(k, v)| format!("{k}=\"{}\"", escape_label_value(v)))
+                    .collect();
+                result.push_str(&format!("{{{}}}", labels.join(",")));
+            }
+            result.push_str(&format!(" {}\n", format_value(sample.value)));
+        }
+    }
+    result
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+pub fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricSink;
+
+    #[test]
+    fn escaping_and_types() {
+        let mut sink = MetricSink::default();
+        sink.gauge_with_labels(
+            "node_hwmon_temp_celsius",
+            "Back\\slash and\nnewline, \"quotes\" stay",
+            &[("chip", "a\"b"), ("label", "c\\d\ne")],
+            42.5,
+        );
+        sink.counter(
+            "procstat_ctxt",
+            "Total number of context switches",
+            241585287.0,
+        );
+        sink.gauge_with_labels("special", "Special values", &[("v", "nan")], f64::NAN);
+        sink.gauge_with_labels("special", "Special values", &[("v", "inf")], f64::INFINITY);
+        sink.gauge_with_labels(
+            "special",
+            "Special values",
+            &[("v", "-inf")],
+            -f64::INFINITY,
+        );
+
+        assert_eq!(
+            encode(sink.families()),
+            r#"# HELP node_hwmon_temp_celsius Back\\slash and\nnewline, "quotes" stay
+# TYPE node_hwmon_temp_celsius gauge
+node_hwmon_temp_celsius{chip="a\"b",label="c\\d\ne"} 42.5
+# HELP procstat_ctxt Total number of context switches
+# TYPE procstat_ctxt counter
+procstat_ctxt 241585287
+# HELP special Special values
+# TYPE special gauge
+special{v="nan"} NaN
+special{v="inf"} +Inf
+special{v="-inf"} -Inf
+"#
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(encode(MetricSink::default().families()), "");
+    }
+}
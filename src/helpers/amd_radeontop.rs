@@ -1,9 +1,13 @@
+use crate::collector::{Collector, CollectorError};
+use crate::config::AmdRadeontopConfig;
+use crate::metrics::MetricSink;
 use regex::Captures;
 use regex::Regex;
 use serde::Deserialize;
 use std::io::prelude::*;
 use std::io::BufReader;
-use std::process::{Command, Stdio};
+use std::num::ParseFloatError;
+use std::process::{Child, Command, Stdio};
 use std::sync::LazyLock;
 use std::sync::Mutex;
 use std::thread;
@@ -11,7 +15,8 @@ use std::thread;
 use std::time::SystemTime;
 
 const RADEONTOP_PRELUDE: &str = "Dumping to -, until termination.";
-const MAX_RESULT_LIFE: u64 = 20;
+/// Seconds on top of two sample intervals before the last sample counts as stale
+const RESULT_LIFE_SLACK: u64 = 5;
 
 static CURRENT_STATS: LazyLock<Mutex<Option<Stats>>> = LazyLock::new(|| Mutex::new(None));
 static CURRENT_CLK_STATS: LazyLock<Mutex<Option<ClkStats>>> = LazyLock::new(|| Mutex::new(None));
@@ -34,32 +39,74 @@ fn get_sys_time_in_secs() -> u64 {
     }
 }
 
-pub fn init() {
+pub struct AmdRadeontop {
+    interval: u32,
+    child: Option<Child>,
+}
+
+impl AmdRadeontop {
+    pub fn new(config: &AmdRadeontopConfig) -> AmdRadeontop {
+        AmdRadeontop {
+            interval: config.interval,
+            child: None,
+        }
+    }
+}
+
+impl Drop for AmdRadeontop {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            println!("Killing radeontop process {}", child.id());
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Collector for AmdRadeontop {
+    fn name(&self) -> &'static str {
+        "amd_radeontop"
+    }
+
+    fn init(&mut self) -> Result<(), CollectorError> {
+        self.child = Some(init(self.interval)?);
+        Ok(())
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        // Tolerates one missed sample
+        let max_result_life = 2 * u64::from(self.interval) + RESULT_LIFE_SLACK;
+        get_radeontop_stats(max_result_life, sink)
+    }
+}
+
+fn init(interval: u32) -> Result<Child, CollectorError> {
     println!("Spawning radeontop process");
     let mut child = Command::new("radeontop")
         .arg("-d")
         .arg("-")
         .arg("-i")
-        .arg("15")
+        .arg(interval.to_string())
         .arg("-t")
         .arg("1")
         .stdout(Stdio::piped())
         .spawn()
-        .expect("Failed to start radeontop command");
+        .map_err(|e| CollectorError::Unavailable(format!("failed to start radeontop: {e}")))?;
 
     let child_id = child.id();
     println!("Child id is {child_id}");
 
-    let status_code = child
-        .try_wait()
-        .unwrap_or_else(|_| panic!("Could not get status of child process {child_id}"));
-    if status_code.is_some() {
-        panic!("Expected the child {child_id} to not finish but it looks like it finished");
+    if let Some(status) = child.try_wait()? {
+        return Err(CollectorError::Unavailable(format!(
+            "expected the child {child_id} to not finish but it exited with {status}"
+        )));
     }
 
-    let stdout = child
-        .stdout
-        .unwrap_or_else(|| panic!("Could not get stdout of {child_id}"));
+    let Some(stdout) = child.stdout.take() else {
+        return Err(CollectorError::Unavailable(format!(
+            "could not get stdout of {child_id}"
+        )));
+    };
 
     thread::spawn(move || {
         let reader = BufReader::new(stdout);
@@ -67,58 +114,67 @@ pub fn init() {
         reader.lines().map_while(Result::ok).for_each(|line| {
             println!("{line}");
             if !line.eq(RADEONTOP_PRELUDE) {
-                if let Some(m) = RADEONTOP_LINE_PATTERN.captures(&line) {
-                    let mut current_stats = CURRENT_STATS.lock().unwrap();
-                    let mut last_update = LAST_UPDATE.lock().unwrap();
-                    *current_stats = Some(parse_stdout_line(m));
-                    *last_update = get_sys_time_in_secs();
-
-                    // mclk and sclk results are sometimes not returned. Missing them,
-                    // is not problematic.
-                    if let Some(m) = RADEONTOP_CLK_PATTERN.captures(&line) {
-                        let mut current_clk_stats = CURRENT_CLK_STATS.lock().unwrap();
-                        *current_clk_stats = Some(parse_clk_captures(m));
+                match RADEONTOP_LINE_PATTERN
+                    .captures(&line)
+                    .map(parse_stdout_line)
+                {
+                    Some(Ok(stats)) => {
+                        let mut current_stats = CURRENT_STATS.lock().unwrap();
+                        let mut last_update = LAST_UPDATE.lock().unwrap();
+                        *current_stats = Some(stats);
+                        *last_update = get_sys_time_in_secs();
+
+                        // mclk and sclk results are sometimes not returned. Missing them,
+                        // is not problematic.
+                        if let Some(Ok(clk_stats)) = RADEONTOP_CLK_PATTERN
+                            .captures(&line)
+                            .map(parse_clk_captures)
+                        {
+                            let mut current_clk_stats = CURRENT_CLK_STATS.lock().unwrap();
+                            *current_clk_stats = Some(clk_stats);
+                        }
                     }
-                } else {
-                    // TODO: make sure to panic the whole process and not only this thread
-                    panic!("Could not parse {line}")
+                    // Stale stats are reported as a collector failure, keep reading
+                    _ => println!("Could not parse radeontop line {line}"),
                 }
             }
         });
+        println!("radeontop stdout closed");
     });
+    Ok(child)
 }
 
-fn parse_stdout_line(captures: Captures) -> Stats {
-    Stats {
-        gpu: to_f64(&captures[1]),
-        ee: to_f64(&captures[2]),
-        vgt: to_f64(&captures[3]),
-        ta: to_f64(&captures[4]),
-        sx: to_f64(&captures[5]),
-        sh: to_f64(&captures[6]),
-        spi: to_f64(&captures[7]),
-        sc: to_f64(&captures[8]),
-        pa: to_f64(&captures[9]),
-        db: to_f64(&captures[10]),
-        cb: to_f64(&captures[11]),
-        vram_percent: to_f64(&captures[12]),
-        vram: to_f64(&captures[13]),
-        gtt_percent: to_f64(&captures[14]),
-        gtt: to_f64(&captures[15]),
-    }
+fn parse_stdout_line(captures: Captures) -> Result<Stats, ParseFloatError> {
+    Ok(Stats {
+        gpu: to_f64(&captures[1])?,
+        ee: to_f64(&captures[2])?,
+        vgt: to_f64(&captures[3])?,
+        ta: to_f64(&captures[4])?,
+        sx: to_f64(&captures[5])?,
+        sh: to_f64(&captures[6])?,
+        spi: to_f64(&captures[7])?,
+        sc: to_f64(&captures[8])?,
+        pa: to_f64(&captures[9])?,
+        db: to_f64(&captures[10])?,
+        cb: to_f64(&captures[11])?,
+        vram_percent: to_f64(&captures[12])?,
+        vram: to_f64(&captures[13])?,
+        gtt_percent: to_f64(&captures[14])?,
+        gtt: to_f64(&captures[15])?,
+    })
 }
 
-fn parse_clk_captures(captures: Captures) -> ClkStats {
-    ClkStats {
-        mclk_percent: to_f64(&captures[1]),
-        mclk: to_f64(&captures[2]),
-        sclk_percent: to_f64(&captures[3]),
-        sclk: to_f64(&captures[4]),
-    }
+fn parse_clk_captures(captures: Captures) -> Result<ClkStats, ParseFloatError> {
+    Ok(ClkStats {
+        mclk_percent: to_f64(&captures[1])?,
+        mclk: to_f64(&captures[2])?,
+        sclk_percent: to_f64(&captures[3])?,
+        sclk: to_f64(&captures[4])?,
+    })
 }
 
-fn to_f64(input: &str) -> f64 {
-    input.parse::<f64>().unwrap()
+fn to_f64(input: &str) -> Result<f64, ParseFloatError> {
+    input.parse::<f64>()
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -148,60 +204,116 @@ struct ClkStats {
     sclk: f64,
 }
 
-pub fn get_radeontop_stats() -> String {
+fn get_radeontop_stats(max_result_life: u64, sink: &mut MetricSink) -> Result<(), CollectorError> {
     let current_stats = CURRENT_STATS.lock().unwrap();
     let last_update = LAST_UPDATE.lock().unwrap();
-    if current_stats.is_none() {
-        return "".to_string();
-    }
+    let Some(stats) = current_stats.as_ref() else {
+        return Err(CollectorError::Unavailable(
+            "no output from radeontop yet".to_string(),
+        ));
+    };
     let current_clk_stats = CURRENT_CLK_STATS.lock().unwrap();
 
     // If the result is too outdated
     let current_time = get_sys_time_in_secs();
-    if current_time - *last_update > MAX_RESULT_LIFE {
-        return "".to_string();
+    if current_time.saturating_sub(*last_update) > max_result_life {
+        return Err(CollectorError::Unavailable(format!(
+            "no output from radeontop for more than {max_result_life} seconds"
+        )));
     }
 
-    let stats = current_stats.as_ref().unwrap();
-
-    let mut result = String::new();
-    result.push_str(&format!("amdgpu_radeontop_gpu {}\n", stats.gpu));
-    result.push_str(&format!("amdgpu_radeontop_ee {}\n", stats.ee));
-    result.push_str(&format!("amdgpu_radeontop_vgt {}\n", stats.vgt));
-    result.push_str(&format!("amdgpu_radeontop_ta {}\n", stats.ta));
-    result.push_str(&format!("amdgpu_radeontop_sx {}\n", stats.sx));
-    result.push_str(&format!("amdgpu_radeontop_sh {}\n", stats.sh));
-    result.push_str(&format!("amdgpu_radeontop_spi {}\n", stats.spi));
-    result.push_str(&format!("amdgpu_radeontop_sc {}\n", stats.sc));
-    result.push_str(&format!("amdgpu_radeontop_pa {}\n", stats.pa));
-    result.push_str(&format!("amdgpu_radeontop_db {}\n", stats.db));
-    result.push_str(&format!("amdgpu_radeontop_cb {}\n", stats.cb));
-    result.push_str(&format!(
-        "amdgpu_radeontop_vram_percent {}\n",
-        stats.vram_percent
-    ));
-    result.push_str(&format!("amdgpu_radeontop_vram {}\n", stats.vram));
-    result.push_str(&format!(
-        "amdgpu_radeontop_gtt_percent {}\n",
-        stats.gtt_percent
-    ));
-    result.push_str(&format!("amdgpu_radeontop_gtt {}\n", stats.gtt));
-
-    if current_clk_stats.is_none() {
-        return result;
-    }
+    sink.gauge(
+        "amdgpu_radeontop_gpu",
+        "Graphics pipe usage in percent",
+        stats.gpu,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_ee",
+        "Event engine usage in percent",
+        stats.ee,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_vgt",
+        "Vertex grouper and tesselator usage in percent",
+        stats.vgt,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_ta",
+        "Texture addresser usage in percent",
+        stats.ta,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_sx",
+        "Shader export usage in percent",
+        stats.sx,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_sh",
+        "Sequencer instruction cache usage in percent",
+        stats.sh,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_spi",
+        "Shader interpolator usage in percent",
+        stats.spi,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_sc",
+        "Scan converter usage in percent",
+        stats.sc,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_pa",
+        "Primitive assembly usage in percent",
+        stats.pa,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_db",
+        "Depth block usage in percent",
+        stats.db,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_cb",
+        "Color block usage in percent",
+        stats.cb,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_vram_percent",
+        "VRAM usage in percent",
+        stats.vram_percent,
+    );
+    sink.gauge("amdgpu_radeontop_vram", "VRAM usage in MB", stats.vram);
+    sink.gauge(
+        "amdgpu_radeontop_gtt_percent",
+        "GTT usage in percent",
+        stats.gtt_percent,
+    );
+    sink.gauge("amdgpu_radeontop_gtt", "GTT usage in MB", stats.gtt);
+
+    let Some(clk_stats) = current_clk_stats.as_ref() else {
+        return Ok(());
+    };
 
     // Add clk stats
-    let clk_stats = current_clk_stats.as_ref().unwrap();
-    result.push_str(&format!(
-        "amdgpu_radeontop_mclk_percent {}\n",
-        clk_stats.mclk_percent
-    ));
-    result.push_str(&format!("amdgpu_radeontop_mclk {}\n", clk_stats.mclk));
-    result.push_str(&format!(
-        "amdgpu_radeontop_sclk_percent {}\n",
-        clk_stats.sclk_percent
-    ));
-    result.push_str(&format!("amdgpu_radeontop_sclk {}\n", clk_stats.sclk));
-    result
+    sink.gauge(
+        "amdgpu_radeontop_mclk_percent",
+        "Memory clock in percent of max",
+        clk_stats.mclk_percent,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_mclk",
+        "Memory clock in GHz",
+        clk_stats.mclk,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_sclk_percent",
+        "Shader clock in percent of max",
+        clk_stats.sclk_percent,
+    );
+    sink.gauge(
+        "amdgpu_radeontop_sclk",
+        "Shader clock in GHz",
+        clk_stats.sclk,
+    );
+    Ok(())
 }
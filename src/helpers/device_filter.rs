@@ -0,0 +1,29 @@
+// Include or exclude regex for the network device collectors
+use crate::config::DeviceFilterConfig;
+use regex::Regex;
+
+pub struct DeviceFilter {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+}
+
+impl DeviceFilter {
+    pub fn new(config: &DeviceFilterConfig) -> DeviceFilter {
+        // Checked by Config::validate
+        let compile = |pattern: &String| Regex::new(pattern).expect("invalid device pattern");
+        DeviceFilter {
+            include: config.device_include.as_ref().map(compile),
+            exclude: config.device_exclude.as_ref().map(compile),
+        }
+    }
+
+    pub fn is_ignored(&self, device: &str) -> bool {
+        if let Some(include) = &self.include {
+            return !include.is_match(device);
+        }
+        if let Some(exclude) = &self.exclude {
+            return exclude.is_match(device);
+        }
+        false
+    }
+}
@@ -1,35 +1,80 @@
+use crate::collector::{Collector, CollectorError};
+use crate::config::HddtempConfig;
+use crate::metrics::MetricSink;
 use regex::Regex;
+use std::io;
 use std::io::prelude::*;
-use std::net::TcpStream;
-use std::sync::LazyLock;
-
-static HDDTEMP_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(
-            r"\|CT1000MX500SSD4\|([0-9.]+)\|C\|.+\|Samsung SSD 860 EVO M.2\|([0-9.]+)\|C\|.+\|WDC WD20EFZX-68AWUN0\|([0-9.]+)\|C\|.+\|WDC WD20EFZX-68AWUN0\|([0-9.]+)\|C\|.*$"
-        )
-        .unwrap()
-});
-
-pub fn get_hddtemp_metrics() -> String {
-    // hddtemp service is listening on port 7634
-    let mut stream =
-        TcpStream::connect("127.0.0.1:7634").expect("could not connect to hddtemp service");
-
-    let mut v: Vec<u8> = Vec::new();
-    stream
-        .read_to_end(&mut v)
-        .expect("hddtemp service did not respond properly");
-    let res = String::from_utf8(v).expect("could not parse");
-
-    if let Some(m) = HDDTEMP_PATTERN.captures(&res) {
-        format!(
-            "hddtemp_crucial_mx500_temp {}\nhddtemp_samsung_860_evo_temp {}\nhddtemp_wd_red_plus_1_temp {}\nhddtemp_wd_red_plus_2_temp {}\n",
-            &m[1],
-            &m[2],
-            &m[3],
-            &m[4],
-        )
-    } else {
-        panic!("Could not parse hddtemp output")
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+pub struct Hddtemp {
+    address: String,
+    pattern: Regex,
+    metrics: Vec<String>,
+    timeout: Duration,
+}
+
+impl Collector for Hddtemp {
+    fn name(&self) -> &'static str {
+        "hddtemp"
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        self.get_hddtemp_metrics(sink)
+    }
+}
+
+impl Hddtemp {
+    pub fn new(config: &HddtempConfig) -> Hddtemp {
+        Hddtemp {
+            address: config.address.clone(),
+            // Checked by Config::validate
+            pattern: Regex::new(&config.pattern).expect("invalid hddtemp pattern"),
+            metrics: config.metrics.clone(),
+            // Checked by Config::validate
+            timeout: Duration::from_secs_f64(config.timeout),
+        }
+    }
+
+    /// A daemon which accepts but never answers would otherwise keep the collector
+    /// busy for every later scrape
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for addr in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "address resolved to nothing")
+        }))
+    }
+
+    fn get_hddtemp_metrics(&self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        let mut stream = self.connect().map_err(|e| {
+            CollectorError::Unavailable(format!("could not connect to hddtemp service: {e}"))
+        })?;
+
+        let mut v: Vec<u8> = Vec::new();
+        stream.read_to_end(&mut v)?;
+        let res = String::from_utf8(v)
+            .map_err(|_| CollectorError::Parse("hddtemp output is not utf-8".to_string()))?;
+
+        let Some(m) = self.pattern.captures(&res) else {
+            return Err(CollectorError::Parse(format!(
+                "could not parse hddtemp output {res}"
+            )));
+        };
+
+        let help = "Drive temperature in Celsius reported by hddtemp";
+        for (idx, metric) in self.metrics.iter().enumerate() {
+            sink.gauge(metric, help, m[idx + 1].parse()?);
+        }
+        Ok(())
     }
 }
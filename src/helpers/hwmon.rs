@@ -0,0 +1,321 @@
+// Reads the hwmon sysfs interface directly, without libsensors
+// https://www.kernel.org/doc/html/latest/hwmon/sysfs-interface.html
+use crate::collector::{Collector, CollectorError};
+use crate::helpers::sysfs::read_trimmed;
+use crate::metrics::{MetricSink, MetricType};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Metric prefix, unit suffix and the divisor from the sysfs unit for a sensor type
+fn sensor_type(name: &str) -> Option<(&'static str, &'static str, f64)> {
+    match name {
+        "temp" => Some(("node_hwmon_temp", "celsius", 1000.0)),
+        "fan" => Some(("node_hwmon_fan", "rpm", 1.0)),
+        "in" => Some(("node_hwmon_in", "volts", 1000.0)),
+        "curr" => Some(("node_hwmon_curr", "amperes", 1000.0)),
+        "power" => Some(("node_hwmon_power", "watts", 1_000_000.0)),
+        "energy" => Some(("node_hwmon_energy", "joules", 1_000_000.0)),
+        "humidity" => Some(("node_hwmon_humidity", "percent", 1000.0)),
+        _ => None,
+    }
+}
+
+/// Splits a file name such as temp1_input into (temp, temp1, input)
+fn split_file_name(file_name: &str) -> Option<(&str, &str, &str)> {
+    let (sensor, item) = file_name.split_once('_')?;
+    let type_len = sensor.find(|c: char| c.is_ascii_digit())?;
+    if !sensor[type_len..].chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((&sensor[..type_len], sensor, item))
+}
+
+/// Replaces everything except alphanumerics with underscores
+fn clean_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Stable chip name derived from the device, since the hwmonN numbers depend on
+/// the probe order, e.g. pci0000_00_0000_00_18_3 for k10temp
+fn chip_name(hwmon: &Path) -> String {
+    if let Ok(device) = fs::canonicalize(hwmon.join("device")) {
+        let name = device.file_name().map(|name| name.to_string_lossy());
+        let parent = device
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .map(|name| name.to_string_lossy());
+        if let (Some(parent), Some(name)) = (parent, name) {
+            return format!("{}_{}", clean_name(&parent), clean_name(&name));
+        }
+    }
+    hwmon
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+pub struct Hwmon {
+    /// Usually /sys/class/hwmon
+    root: PathBuf,
+}
+
+impl Hwmon {
+    /// Reads the hwmon devices below `sysfs`, which can point to a fixture tree
+    pub fn new(sysfs: &Path) -> Hwmon {
+        Hwmon {
+            root: sysfs.join("class/hwmon"),
+        }
+    }
+
+    fn collect_chip(&self, hwmon: &Path, sink: &mut MetricSink) {
+        let chip = chip_name(hwmon);
+        if let Some(name) = read_trimmed(&hwmon.join("name")) {
+            sink.gauge_with_labels(
+                "node_hwmon_chip_names",
+                "Annotation metric for human-readable chip names",
+                &[("chip", &chip), ("chip_name", &name)],
+                1.0,
+            );
+        }
+
+        // Older drivers keep the attributes in the device directory
+        let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for dir in [hwmon.join("device"), hwmon.to_path_buf()] {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.map_while(Result::ok) {
+                files.insert(
+                    entry.file_name().to_string_lossy().into_owned(),
+                    entry.path(),
+                );
+            }
+        }
+
+        for (file_name, path) in files.iter() {
+            let Some((type_name, sensor, item)) = split_file_name(file_name) else {
+                continue;
+            };
+            let Some((prefix, unit, divisor)) = sensor_type(type_name) else {
+                continue;
+            };
+            let (metric, help, metric_type) = match item {
+                "input" if type_name == "energy" => (
+                    format!("{prefix}_{unit}_total"),
+                    format!("Hardware monitor for {type_name} ({item})"),
+                    MetricType::Counter,
+                ),
+                "input" => (
+                    format!("{prefix}_{unit}"),
+                    format!("Hardware monitor for {type_name} ({item})"),
+                    MetricType::Gauge,
+                ),
+                "min" | "max" | "crit" | "average" => (
+                    format!("{prefix}_{item}_{unit}"),
+                    format!("Hardware monitor for {type_name} ({item})"),
+                    MetricType::Gauge,
+                ),
+                "alarm" => (
+                    format!("{prefix}_alarm"),
+                    format!("Hardware sensor alarm status ({type_name})"),
+                    MetricType::Gauge,
+                ),
+                _ => continue,
+            };
+
+            // Sensors of unplugged or sleeping devices fail with EIO or ENODATA
+            let Some(value) = read_trimmed(path).and_then(|value| value.parse::<f64>().ok()) else {
+                continue;
+            };
+            let value = if item == "alarm" {
+                value
+            } else {
+                value / divisor
+            };
+
+            let label = files
+                .get(&format!("{sensor}_label"))
+                .and_then(|path| read_trimmed(path))
+                .unwrap_or_else(|| sensor.to_string());
+            sink.sample(
+                &metric,
+                &help,
+                metric_type,
+                &[("chip", &chip), ("sensor", sensor), ("label", &label)],
+                value,
+            );
+        }
+    }
+}
+
+impl Collector for Hwmon {
+    fn name(&self) -> &'static str {
+        "hwmon"
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        let entries = fs::read_dir(&self.root).map_err(|e| {
+            CollectorError::Unavailable(format!("could not read {}: {e}", self.root.display()))
+        })?;
+        let mut hwmons: Vec<PathBuf> = entries
+            .map_while(Result::ok)
+            .map(|entry| entry.path())
+            .collect();
+        hwmons.sort();
+
+        for hwmon in hwmons.iter() {
+            self.collect_chip(hwmon, sink);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, fixtures, TempTree};
+
+    fn collect(sysfs: &Path) -> Result<MetricSink, CollectorError> {
+        testing::collect(&mut Hwmon::new(sysfs))
+    }
+
+    fn labels<'a>(chip: &'a str, sensor: &'a str, label: &'a str) -> [(&'a str, &'a str); 3] {
+        [("chip", chip), ("sensor", sensor), ("label", label)]
+    }
+
+    #[test]
+    fn fixture() {
+        let sink = collect(&fixtures().join("sys")).unwrap();
+        let k10temp = "pci0000_00_0000_00_18_3";
+        let amdgpu = "pci0000_00_0000_0b_00_0";
+        let kraken = "1_4_1_0_0003_1E71_170E_0003";
+
+        let chip_names = sink.family("node_hwmon_chip_names").unwrap();
+        assert_eq!(chip_names.samples.len(), 4);
+        for (chip, name) in [
+            (k10temp, "k10temp"),
+            (amdgpu, "amdgpu"),
+            (kraken, "kraken2"),
+            ("hwmon3", "nvme"),
+        ] {
+            let labels = [("chip", chip), ("chip_name", name)];
+            assert_eq!(sink.value("node_hwmon_chip_names", &labels), Some(1.0));
+        }
+
+        let temp = "node_hwmon_temp_celsius";
+        assert_eq!(temp, sink.family(temp).unwrap().name);
+        assert_eq!(sink.family(temp).unwrap().metric_type, MetricType::Gauge);
+        assert_eq!(
+            sink.value(temp, &labels(k10temp, "temp1", "Tctl")),
+            Some(45.25)
+        );
+        assert_eq!(
+            sink.value(temp, &labels(k10temp, "temp3", "Tccd1")),
+            Some(38.0)
+        );
+        assert_eq!(
+            sink.value(temp, &labels(amdgpu, "temp2", "junction")),
+            Some(43.0)
+        );
+        // Without a label file the sensor name is used
+        assert_eq!(
+            sink.value(temp, &labels(kraken, "temp1", "temp1")),
+            Some(31.9)
+        );
+        assert_eq!(
+            sink.value(
+                "node_hwmon_temp_crit_celsius",
+                &labels(amdgpu, "temp1", "edge")
+            ),
+            Some(100.0)
+        );
+
+        let rpm = labels(amdgpu, "fan1", "fan1");
+        assert_eq!(sink.value("node_hwmon_fan_rpm", &rpm), Some(0.0));
+        assert_eq!(sink.value("node_hwmon_fan_min_rpm", &rpm), Some(0.0));
+        assert_eq!(sink.value("node_hwmon_fan_max_rpm", &rpm), Some(3300.0));
+        assert_eq!(
+            sink.value("node_hwmon_fan_rpm", &labels(kraken, "fan2", "fan2")),
+            Some(1950.0)
+        );
+        assert_eq!(
+            sink.value("node_hwmon_in_volts", &labels(amdgpu, "in0", "vddgfx")),
+            Some(0.806)
+        );
+        assert_eq!(
+            sink.value(
+                "node_hwmon_power_average_watts",
+                &labels(amdgpu, "power1", "PPT")
+            ),
+            Some(9.0)
+        );
+        // power1_cap is not one of the exported items
+        assert!(sink
+            .families()
+            .iter()
+            .all(|family| !family.name.contains("cap")));
+    }
+
+    #[test]
+    fn missing_input() {
+        let sink = collect(&fixtures().join("sys")).unwrap();
+        // hwmon3 has no device link, so the chip falls back to the hwmon name
+        let composite = labels("hwmon3", "temp1", "Composite");
+        assert_eq!(sink.value("node_hwmon_temp_celsius", &composite), None);
+        assert_eq!(
+            sink.value("node_hwmon_temp_max_celsius", &composite),
+            Some(84.85)
+        );
+        assert_eq!(sink.value("node_hwmon_temp_alarm", &composite), Some(0.0));
+        assert_eq!(
+            sink.value(
+                "node_hwmon_temp_celsius",
+                &labels("hwmon3", "temp2", "Sensor 1")
+            ),
+            Some(35.85)
+        );
+    }
+
+    #[test]
+    fn unreadable_name() {
+        // Reading a directory fails like an attribute of a sleeping device
+        let sysfs = TempTree::new("hwmon-unreadable-name");
+        sysfs
+            .create_dir("class/hwmon/hwmon0/name")
+            .create_dir("class/hwmon/hwmon0/temp1_input")
+            .write("class/hwmon/hwmon0/temp2_input", "51000\n");
+
+        let sink = collect(sysfs.path()).unwrap();
+        assert!(sink.family("node_hwmon_chip_names").is_none());
+        let temp = sink.family("node_hwmon_temp_celsius").unwrap();
+        assert_eq!(temp.samples.len(), 1);
+        assert_eq!(
+            sink.value(
+                "node_hwmon_temp_celsius",
+                &labels("hwmon0", "temp2", "temp2")
+            ),
+            Some(51.0)
+        );
+    }
+
+    #[test]
+    fn missing_sysfs() {
+        let result = collect(&fixtures().join("does-not-exist"));
+        assert!(matches!(result, Err(CollectorError::Unavailable(_))));
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            split_file_name("temp1_input"),
+            Some(("temp", "temp1", "input"))
+        );
+        assert_eq!(split_file_name("in10_max"), Some(("in", "in10", "max")));
+        assert_eq!(split_file_name("name"), None);
+        assert_eq!(split_file_name("update_interval"), None);
+        assert_eq!(split_file_name("temp1a_input"), None);
+    }
+}
@@ -6,80 +6,299 @@
 #![allow(unnecessary_transmutes)]
 // include libsensors bindings generated by bindgen
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+use crate::collector::{Collector, CollectorError};
+use crate::config::LmSensorsConfig;
+use crate::metrics::{MetricSink, MetricType};
 use std::collections::HashMap;
 use std::ffi::CStr;
+use std::fs;
 use std::os::raw;
+use std::path::PathBuf;
 use std::ptr;
 use std::str;
+use std::sync::Mutex;
 
-const FEATURE_INIT_COUNT: i32 = 10;
+// libsensors always reads the sysfs it finds in /proc/mounts, so this does not
+// follow --path.sysfs
+const HWMON_PATH: &str = "/sys/class/hwmon";
 
+/// libsensors has global state, so after a config reload the old and the new
+/// collector briefly exist together. Every sensors_init starts a new generation
+/// and the chip pointers of older generations must not be used anymore.
+struct LibSensors {
+    initialized: bool,
+    generation: u64,
+}
+
+static LIBSENSORS: Mutex<LibSensors> = Mutex::new(LibSensors {
+    initialized: false,
+    generation: 0,
+});
+
+/// Subfeature selected by `relevant_keys`, or one of its limits
 struct SensorValueWrapper {
+    help: String,
+    chip: String,
+    label: String,
+    name: *const sensors_chip_name,
+    subfeature_number: i32,
+}
+
+/// Metric families of one feature type
+struct SensorFamily {
+    /// Name without the unit, e.g. lm_sensors_temp
+    prefix: &'static str,
+    /// Used in the help of limits and alarms
+    quantity: &'static str,
+    unit: &'static str,
+    help: &'static str,
+    metric_type: MetricType,
+    /// Subfeatures holding the value, the first one which exists is used
+    inputs: &'static [sensors_subfeature_type],
+    /// Thresholds exported as `{prefix}_{suffix}_{unit}`
+    limits: &'static [(&'static str, sensors_subfeature_type)],
+    /// Exported as `{prefix}_alarm`
+    alarm: Option<sensors_subfeature_type>,
+}
+
+const TEMP_FAMILY: SensorFamily = SensorFamily {
+    prefix: "lm_sensors_temp",
+    quantity: "temperature",
+    unit: "celsius",
+    help: "Temperature in Celsius",
+    metric_type: MetricType::Gauge,
+    inputs: &[sensors_subfeature_type_SENSORS_SUBFEATURE_TEMP_INPUT],
+    limits: &[
+        ("min", sensors_subfeature_type_SENSORS_SUBFEATURE_TEMP_MIN),
+        ("max", sensors_subfeature_type_SENSORS_SUBFEATURE_TEMP_MAX),
+        ("crit", sensors_subfeature_type_SENSORS_SUBFEATURE_TEMP_CRIT),
+    ],
+    alarm: Some(sensors_subfeature_type_SENSORS_SUBFEATURE_TEMP_ALARM),
+};
+const FAN_FAMILY: SensorFamily = SensorFamily {
+    prefix: "lm_sensors_fan",
+    quantity: "fan speed",
+    unit: "rpm",
+    help: "Fan speed in RPM",
+    metric_type: MetricType::Gauge,
+    inputs: &[sensors_subfeature_type_SENSORS_SUBFEATURE_FAN_INPUT],
+    limits: &[
+        ("min", sensors_subfeature_type_SENSORS_SUBFEATURE_FAN_MIN),
+        ("max", sensors_subfeature_type_SENSORS_SUBFEATURE_FAN_MAX),
+    ],
+    alarm: Some(sensors_subfeature_type_SENSORS_SUBFEATURE_FAN_ALARM),
+};
+const IN_FAMILY: SensorFamily = SensorFamily {
+    prefix: "lm_sensors_in",
+    quantity: "voltage",
+    unit: "volts",
+    help: "Voltage in volts",
+    metric_type: MetricType::Gauge,
+    inputs: &[sensors_subfeature_type_SENSORS_SUBFEATURE_IN_INPUT],
+    limits: &[
+        ("min", sensors_subfeature_type_SENSORS_SUBFEATURE_IN_MIN),
+        ("max", sensors_subfeature_type_SENSORS_SUBFEATURE_IN_MAX),
+        ("crit", sensors_subfeature_type_SENSORS_SUBFEATURE_IN_CRIT),
+    ],
+    alarm: Some(sensors_subfeature_type_SENSORS_SUBFEATURE_IN_ALARM),
+};
+const CURR_FAMILY: SensorFamily = SensorFamily {
+    prefix: "lm_sensors_curr",
+    quantity: "current",
+    unit: "amperes",
+    help: "Current in amperes",
+    metric_type: MetricType::Gauge,
+    inputs: &[sensors_subfeature_type_SENSORS_SUBFEATURE_CURR_INPUT],
+    limits: &[
+        ("min", sensors_subfeature_type_SENSORS_SUBFEATURE_CURR_MIN),
+        ("max", sensors_subfeature_type_SENSORS_SUBFEATURE_CURR_MAX),
+        ("crit", sensors_subfeature_type_SENSORS_SUBFEATURE_CURR_CRIT),
+    ],
+    alarm: Some(sensors_subfeature_type_SENSORS_SUBFEATURE_CURR_ALARM),
+};
+const POWER_FAMILY: SensorFamily = SensorFamily {
+    prefix: "lm_sensors_power",
+    quantity: "power",
+    unit: "watts",
+    help: "Power in watts",
+    metric_type: MetricType::Gauge,
+    // amdgpu only reports the average
+    inputs: &[
+        sensors_subfeature_type_SENSORS_SUBFEATURE_POWER_INPUT,
+        sensors_subfeature_type_SENSORS_SUBFEATURE_POWER_AVERAGE,
+    ],
+    limits: &[
+        ("min", sensors_subfeature_type_SENSORS_SUBFEATURE_POWER_MIN),
+        ("max", sensors_subfeature_type_SENSORS_SUBFEATURE_POWER_MAX),
+        (
+            "crit",
+            sensors_subfeature_type_SENSORS_SUBFEATURE_POWER_CRIT,
+        ),
+    ],
+    alarm: Some(sensors_subfeature_type_SENSORS_SUBFEATURE_POWER_ALARM),
+};
+const ENERGY_FAMILY: SensorFamily = SensorFamily {
+    prefix: "lm_sensors_energy",
+    quantity: "energy",
+    unit: "joules_total",
+    help: "Energy consumed in joules",
+    metric_type: MetricType::Counter,
+    inputs: &[sensors_subfeature_type_SENSORS_SUBFEATURE_ENERGY_INPUT],
+    limits: &[],
+    alarm: None,
+};
+const HUMIDITY_FAMILY: SensorFamily = SensorFamily {
+    prefix: "lm_sensors_humidity",
+    quantity: "humidity",
+    unit: "percent",
+    help: "Relative humidity in percent",
+    metric_type: MetricType::Gauge,
+    inputs: &[sensors_subfeature_type_SENSORS_SUBFEATURE_HUMIDITY_INPUT],
+    limits: &[],
+    alarm: None,
+};
+
+fn sensor_family(feature_type: sensors_feature_type) -> Option<&'static SensorFamily> {
+    match feature_type {
+        sensors_feature_type_SENSORS_FEATURE_TEMP => Some(&TEMP_FAMILY),
+        sensors_feature_type_SENSORS_FEATURE_FAN => Some(&FAN_FAMILY),
+        sensors_feature_type_SENSORS_FEATURE_IN => Some(&IN_FAMILY),
+        sensors_feature_type_SENSORS_FEATURE_CURR => Some(&CURR_FAMILY),
+        sensors_feature_type_SENSORS_FEATURE_POWER => Some(&POWER_FAMILY),
+        sensors_feature_type_SENSORS_FEATURE_ENERGY => Some(&ENERGY_FAMILY),
+        sensors_feature_type_SENSORS_FEATURE_HUMIDITY => Some(&HUMIDITY_FAMILY),
+        _ => None,
+    }
+}
+
+/// Subfeature exported with chip, sensor and label labels when `export_all` is set
+struct LabeledSensor {
+    metric: String,
+    help: String,
+    metric_type: MetricType,
+    chip: String,
+    sensor: String,
+    label: String,
     name: *const sensors_chip_name,
     subfeature_number: i32,
 }
 
 pub struct LmSensors {
+    /// Metric names by `chip prefix/subfeature name`
+    relevant_keys: HashMap<String, String>,
+    export_all: bool,
     subfeatures: HashMap<String, SensorValueWrapper>,
-    init_count: i32,
+    labeled: Vec<LabeledSensor>,
+    /// Devices behind /sys/class/hwmon when libsensors was last scanned
+    hwmon_devices: Vec<PathBuf>,
+    /// Generation of libsensors which `subfeatures` belong to
+    generation: u64,
 }
 
-pub fn get_lm_sensors() -> LmSensors {
+pub fn get_lm_sensors(config: &LmSensorsConfig) -> LmSensors {
     LmSensors {
+        relevant_keys: config.relevant_keys.clone(),
+        export_all: config.export_all,
         subfeatures: HashMap::new(),
-        init_count: 0,
+        labeled: Vec::new(),
+        hwmon_devices: Vec::new(),
+        generation: 0,
     }
 }
 
+/// Sorted targets of the /sys/class/hwmon links. The hwmonN names are reused, so
+/// the device paths are compared to notice a different device taking a number.
+fn hwmon_devices() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(HWMON_PATH) else {
+        return Vec::new();
+    };
+    let mut devices: Vec<PathBuf> = entries
+        .map_while(Result::ok)
+        .map(|entry| fs::read_link(entry.path()).unwrap_or(entry.path()))
+        .collect();
+    devices.sort();
+    devices
+}
+
+/// Full chip name such as k10temp-pci-00c3
+unsafe fn chip_name(chip: *const sensors_chip_name) -> String {
+    let mut buf = [0 as raw::c_char; 256];
+    if sensors_snprintf_chip_name(buf.as_mut_ptr(), buf.len(), chip) < 0 {
+        return CStr::from_ptr((*chip).prefix)
+            .to_string_lossy()
+            .into_owned();
+    }
+    CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+}
+
+/// Label from sensors.conf, or the feature name if there is none
+unsafe fn feature_label(chip: *const sensors_chip_name, feature: *const sensors_feature) -> String {
+    let label = sensors_get_label(chip, feature);
+    if label.is_null() {
+        return CStr::from_ptr((*feature).name)
+            .to_string_lossy()
+            .into_owned();
+    }
+    let result = CStr::from_ptr(label).to_string_lossy().into_owned();
+    libc::free(label as *mut raw::c_void);
+    result
+}
+
+// The chip pointers are owned by libsensors and are only used while holding
+// LIBSENSORS and only if their generation is still the current one
+unsafe impl Send for LmSensors {}
+
 impl Drop for LmSensors {
     fn drop(&mut self) {
-        if !self.subfeatures.is_empty() {
+        let mut libsensors = LIBSENSORS.lock().unwrap_or_else(|e| e.into_inner());
+        if libsensors.initialized && libsensors.generation == self.generation {
             println!("executing lm_sensors cleanup");
             unsafe {
                 sensors_cleanup();
             }
+            libsensors.initialized = false;
         }
     }
 }
 
+impl Collector for LmSensors {
+    fn name(&self) -> &'static str {
+        "lm_sensors"
+    }
+
+    fn init(&mut self) -> Result<(), CollectorError> {
+        self.init()
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        self.get_lm_sensor_metrics(sink)
+    }
+}
+
 impl LmSensors {
-    pub fn init(&mut self) {
+    pub fn init(&mut self) -> Result<(), CollectorError> {
         let mut subfeature_map: HashMap<String, SensorValueWrapper> = HashMap::new();
+        let mut labeled = Vec::new();
 
-        let relevant_keys: HashMap<&str, &str> = vec![
-            ("k10temp/temp1_input", "lm_sensors_tctl"),
-            ("k10temp/temp2_input", "lm_sensors_tdie"),
-            ("asusec/temp2_input", "lm_sensors_cpu_temp"),
-            ("asusec/temp3_input", "lm_sensors_mb_temp"),
-            ("asusec/temp1_input", "lm_sensors_chipset_temp"),
-            // NZXT kraken2 data
-            ("kraken2/temp1_input", "aio_liquid_temp"),
-            ("kraken2/fan1_input", "aio_fan_speed"),
-            ("kraken2/fan2_input", "aio_pump_speed"),
-            // amdgpu sensors
-            ("amdgpu/in0_input", "amdgpu_vddgfx_voltage"),
-            ("amdgpu/fan1_input", "amdgpu_fan1_speed"),
-            ("amdgpu/temp2_input", "amdgpu_junction_temp"),
-            ("amdgpu/temp3_input", "amdgpu_mem_temp"),
-            ("amdgpu/power1_average", "amdgpu_slowppt_wattage"),
-            // wireless adapater
-            ("iwlwifi_1/temp1_input", "wireless_temp"),
-            // logitech mouse voltage
-            ("hidpp_battery_0/in0_input", "mouse_battery_voltage"),
-        ]
-        .into_iter()
-        .collect();
-
+        // Taken before scanning so that a device appearing during the scan
+        // triggers another one
+        let hwmon_devices = hwmon_devices();
+        let mut libsensors = LIBSENSORS.lock().unwrap_or_else(|e| e.into_inner());
         unsafe {
             println!("lm_sensors initialization started");
-            if self.init_count > 0 {
+            if libsensors.initialized {
                 println!("calling sensors_cleanup");
                 sensors_cleanup();
+                libsensors.initialized = false;
             }
             println!("calling sensors_init(null)");
             if sensors_init(ptr::null_mut()) != 0 {
-                panic!("lm_sensors init failed");
+                return Err(CollectorError::Unavailable(
+                    "lm_sensors init failed".to_string(),
+                ));
             }
+            libsensors.initialized = true;
+            libsensors.generation += 1;
 
             let mut chip_next: raw::c_int = 0;
             let chip_next_ptr: *mut raw::c_int = &mut chip_next;
@@ -89,9 +308,10 @@ impl LmSensors {
                     break;
                 }
 
-                let prefix = CStr::from_ptr((*chip).prefix).to_str().unwrap();
-                let path = CStr::from_ptr((*chip).path).to_str().unwrap();
+                let prefix = CStr::from_ptr((*chip).prefix).to_string_lossy();
+                let path = CStr::from_ptr((*chip).path).to_string_lossy();
                 println!("Found chip with prefix {prefix} and path {path}");
+                let full_chip_name = chip_name(chip);
 
                 let mut feature_next: raw::c_int = 0;
                 let feature_next_ptr: *mut raw::c_int = &mut feature_next;
@@ -102,9 +322,64 @@ impl LmSensors {
                         break;
                     }
 
-                    let feature_name = CStr::from_ptr((*feature).name).to_str().unwrap();
+                    let feature_name = CStr::from_ptr((*feature).name).to_string_lossy();
                     println!("Found feature {feature_name} from chip {prefix}");
 
+                    let label = feature_label(chip, feature);
+                    let family = sensor_family((*feature).type_);
+                    let readable = |subfeature_type| {
+                        let subfeature = sensors_get_subfeature(chip, feature, subfeature_type);
+                        if !subfeature.is_null() && ((*subfeature).flags & SENSORS_MODE_R) != 0 {
+                            Some((*subfeature).number)
+                        } else {
+                            None
+                        }
+                    };
+                    if self.export_all {
+                        if let Some(family) = family {
+                            let mut push = |metric: String, help: String, metric_type, number| {
+                                labeled.push(LabeledSensor {
+                                    metric,
+                                    help,
+                                    metric_type,
+                                    chip: full_chip_name.clone(),
+                                    sensor: feature_name.to_string(),
+                                    label: label.clone(),
+                                    name: chip,
+                                    subfeature_number: number,
+                                })
+                            };
+
+                            match family.inputs.iter().find_map(|input| readable(*input)) {
+                                Some(number) => push(
+                                    format!("{}_{}", family.prefix, family.unit),
+                                    family.help.to_string(),
+                                    family.metric_type,
+                                    number,
+                                ),
+                                None => println!("No readable input for {prefix}/{feature_name}"),
+                            }
+                            for (suffix, subfeature_type) in family.limits.iter() {
+                                if let Some(number) = readable(*subfeature_type) {
+                                    push(
+                                        format!("{}_{suffix}_{}", family.prefix, family.unit),
+                                        format!("Configured {suffix} {}", family.quantity),
+                                        MetricType::Gauge,
+                                        number,
+                                    );
+                                }
+                            }
+                            if let Some(number) = family.alarm.and_then(readable) {
+                                push(
+                                    format!("{}_alarm", family.prefix),
+                                    format!("Whether the {} alarm is raised", family.quantity),
+                                    MetricType::Gauge,
+                                    number,
+                                );
+                            }
+                        }
+                    }
+
                     let mut subfeature_next: raw::c_int = 0;
                     let subfeature_next_ptr: *mut raw::c_int = &mut subfeature_next;
 
@@ -114,7 +389,7 @@ impl LmSensors {
                         if subfeature.is_null() {
                             break;
                         }
-                        let subfeature_name = CStr::from_ptr((*subfeature).name).to_str().unwrap();
+                        let subfeature_name = CStr::from_ptr((*subfeature).name).to_string_lossy();
                         println!(
                             "\t\tFound subfeature_name {} with number {}",
                             subfeature_name,
@@ -123,15 +398,46 @@ impl LmSensors {
 
                         if ((*subfeature).flags & SENSORS_MODE_R) != 0 {
                             let key = format!("{prefix}/{subfeature_name}");
-                            if relevant_keys.contains_key(key.as_str()) {
+                            if let Some(metric) = self.relevant_keys.get(&key) {
                                 println!("Found interesting key {prefix}/{subfeature_name}");
-                                subfeature_map.insert(
-                                    relevant_keys.get(key.as_str()).unwrap().to_string(),
-                                    SensorValueWrapper {
-                                        name: chip,
-                                        subfeature_number: (*subfeature).number,
-                                    },
+                                let mut insert = |metric: String, help: String, number| {
+                                    subfeature_map.insert(
+                                        metric,
+                                        SensorValueWrapper {
+                                            help,
+                                            chip: full_chip_name.clone(),
+                                            label: label.clone(),
+                                            name: chip,
+                                            subfeature_number: number,
+                                        },
+                                    );
+                                };
+                                insert(
+                                    metric.clone(),
+                                    format!("lm_sensors value of {key}"),
+                                    (*subfeature).number,
                                 );
+                                // The limits of an input as e.g. lm_sensors_tctl_max
+                                let family = family
+                                    .filter(|family| family.inputs.contains(&(*subfeature).type_));
+                                if let Some(family) = family {
+                                    for (suffix, subfeature_type) in family.limits.iter() {
+                                        if let Some(number) = readable(*subfeature_type) {
+                                            insert(
+                                                format!("{metric}_{suffix}"),
+                                                format!("Configured {suffix} of {key}"),
+                                                number,
+                                            );
+                                        }
+                                    }
+                                    if let Some(number) = family.alarm.and_then(readable) {
+                                        insert(
+                                            format!("{metric}_alarm"),
+                                            format!("Whether the alarm of {key} is raised"),
+                                            number,
+                                        );
+                                    }
+                                }
                             } else {
                                 println!("Ignoring key {prefix}/{subfeature_name}");
                             }
@@ -144,13 +450,23 @@ impl LmSensors {
             println!("lm_sensors initialization complete");
         }
         self.subfeatures = subfeature_map;
-        self.init_count += 1;
+        self.labeled = labeled;
+        self.hwmon_devices = hwmon_devices;
+        self.generation = libsensors.generation;
+        Ok(())
     }
 
-    pub fn get_lm_sensor_metrics(&mut self) -> String {
-        let mut result = String::new();
-        if self.init_count < FEATURE_INIT_COUNT {
-            self.init()
+    pub fn get_lm_sensor_metrics(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        // Chips such as kraken2 or hidpp_battery_0 come and go with their USB devices
+        if hwmon_devices() != self.hwmon_devices {
+            println!("hwmon devices changed, rescanning lm_sensors chips");
+            self.init()?;
+        }
+        let libsensors = LIBSENSORS.lock().unwrap_or_else(|e| e.into_inner());
+        if !libsensors.initialized || libsensors.generation != self.generation {
+            return Err(CollectorError::Unavailable(
+                "lm_sensors was reinitialized by another collector".to_string(),
+            ));
         }
         for (key, subfeature) in self.subfeatures.iter() {
             let mut value = 0f64;
@@ -162,8 +478,36 @@ impl LmSensors {
                     continue;
                 }
             }
-            result.push_str(&format!("{key} {value}\n"));
+            sink.gauge_with_labels(
+                key,
+                &subfeature.help,
+                &[("chip", &subfeature.chip), ("label", &subfeature.label)],
+                value,
+            );
+        }
+        for sensor in self.labeled.iter() {
+            let mut value = 0f64;
+            unsafe {
+                if sensors_get_value(sensor.name, sensor.subfeature_number, &mut value) != 0 {
+                    println!(
+                        "Could not get sensor value {}/{}",
+                        sensor.chip, sensor.sensor
+                    );
+                    continue;
+                }
+            }
+            sink.sample(
+                &sensor.metric,
+                &sensor.help,
+                sensor.metric_type,
+                &[
+                    ("chip", &sensor.chip),
+                    ("sensor", &sensor.sensor),
+                    ("label", &sensor.label),
+                ],
+                value,
+            );
         }
-        result
+        Ok(())
     }
 }
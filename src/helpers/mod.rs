@@ -1,8 +1,20 @@
+#[cfg(feature = "radeontop")]
 pub mod amd_radeontop;
+pub mod device_filter;
+#[cfg(feature = "hddtemp")]
 pub mod hddtemp;
+pub mod hwmon;
+#[cfg(feature = "libsensors")]
 pub mod lm_sensors;
+pub mod net_class;
+#[cfg(feature = "nvidia")]
 pub mod nvidia;
+#[cfg(feature = "hidapi")]
 pub mod nzxt_aio;
+#[cfg(feature = "procfs")]
 pub mod proc_meminfo;
+#[cfg(feature = "procfs")]
 pub mod proc_netdev;
+#[cfg(feature = "procfs")]
 pub mod proc_stat;
+pub mod sysfs;
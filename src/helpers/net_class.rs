@@ -0,0 +1,265 @@
+// Interface state from /sys/class/net, which /proc/net/dev does not have
+// https://www.kernel.org/doc/Documentation/ABI/testing/sysfs-class-net
+use crate::collector::{Collector, CollectorError};
+use crate::config::DeviceFilterConfig;
+use crate::helpers::device_filter::DeviceFilter;
+use crate::helpers::sysfs::read_trimmed;
+use crate::metrics::MetricSink;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Attributes such as carrier and speed fail with EINVAL while the interface is down
+fn read_number(path: &Path) -> Option<f64> {
+    read_trimmed(path).and_then(|value| value.parse().ok())
+}
+
+pub struct NetClass {
+    /// Usually /sys/class/net
+    root: PathBuf,
+    devices: DeviceFilter,
+}
+
+impl NetClass {
+    pub fn new(sysfs: &Path, config: &DeviceFilterConfig) -> NetClass {
+        NetClass {
+            root: sysfs.join("class/net"),
+            devices: DeviceFilter::new(config),
+        }
+    }
+
+    fn collect_device(&self, path: &Path, device: &str, sink: &mut MetricSink) {
+        let operstate = read_trimmed(&path.join("operstate")).unwrap_or_default();
+        let address = read_trimmed(&path.join("address")).unwrap_or_default();
+        let duplex = read_trimmed(&path.join("duplex")).unwrap_or_default();
+        sink.gauge_with_labels(
+            "node_network_info",
+            "Non-numeric data of the network interface, value is always 1",
+            &[
+                ("device", device),
+                ("operstate", &operstate),
+                ("address", &address),
+                ("duplex", &duplex),
+            ],
+            1.0,
+        );
+        sink.gauge_with_labels(
+            "node_network_up",
+            "Whether the operstate of the interface is up",
+            &[("device", device)],
+            if operstate == "up" { 1.0 } else { 0.0 },
+        );
+
+        let labels = [("device", device)];
+        if let Some(carrier) = read_number(&path.join("carrier")) {
+            sink.gauge_with_labels(
+                "node_network_carrier",
+                "Whether the interface has a physical link",
+                &labels,
+                carrier,
+            );
+        }
+        // -1 for virtual devices and links without autonegotiation result
+        if let Some(speed) = read_number(&path.join("speed")).filter(|speed| *speed >= 0.0) {
+            sink.gauge_with_labels(
+                "node_network_speed_bytes",
+                "Negotiated link speed in bytes per second",
+                &labels,
+                speed * 1_000_000.0 / 8.0,
+            );
+        }
+        if let Some(mtu) = read_number(&path.join("mtu")) {
+            sink.gauge_with_labels(
+                "node_network_mtu_bytes",
+                "MTU of the interface",
+                &labels,
+                mtu,
+            );
+        }
+        for (file, name, help) in [
+            (
+                "carrier_changes",
+                "node_network_carrier_changes_total",
+                "Number of times the link went up or down",
+            ),
+            (
+                "carrier_up_count",
+                "node_network_carrier_up_changes_total",
+                "Number of times the link went up",
+            ),
+            (
+                "carrier_down_count",
+                "node_network_carrier_down_changes_total",
+                "Number of times the link went down",
+            ),
+        ] {
+            if let Some(value) = read_number(&path.join(file)) {
+                sink.counter_with_labels(name, help, &labels, value);
+            }
+        }
+    }
+}
+
+impl Collector for NetClass {
+    fn name(&self) -> &'static str {
+        "net_class"
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        let entries = fs::read_dir(&self.root).map_err(|e| {
+            CollectorError::Unavailable(format!("could not read {}: {e}", self.root.display()))
+        })?;
+        let mut devices: Vec<(String, PathBuf)> = entries
+            .filter_map(Result::ok)
+            // Interfaces are symlinks, but there are also files such as bonding_masters
+            .filter(|entry| {
+                entry
+                    .file_type()
+                    .is_ok_and(|file_type| !file_type.is_file())
+            })
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().into_owned(),
+                    entry.path(),
+                )
+            })
+            .filter(|(device, _)| !self.devices.is_ignored(device))
+            .collect();
+        devices.sort();
+
+        for (device, path) in devices.iter() {
+            self.collect_device(path, device, sink);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempTree};
+
+    fn sysfs() -> PathBuf {
+        testing::fixtures().join("sys")
+    }
+
+    fn collect(sysfs: &Path, config: &DeviceFilterConfig) -> Result<MetricSink, CollectorError> {
+        testing::collect(&mut NetClass::new(sysfs, config))
+    }
+
+    #[test]
+    fn fixture() {
+        let sink = collect(&sysfs(), &DeviceFilterConfig::default()).unwrap();
+        let enp5s0 = [("device", "enp5s0")];
+        assert_eq!(
+            sink.value(
+                "node_network_info",
+                &[
+                    ("device", "enp5s0"),
+                    ("operstate", "up"),
+                    ("address", "04:42:1a:0c:3f:7e"),
+                    ("duplex", "full"),
+                ]
+            ),
+            Some(1.0)
+        );
+        assert_eq!(sink.value("node_network_up", &enp5s0), Some(1.0));
+        assert_eq!(sink.value("node_network_carrier", &enp5s0), Some(1.0));
+        assert_eq!(
+            sink.value("node_network_speed_bytes", &enp5s0),
+            Some(12_500_000.0)
+        );
+        assert_eq!(sink.value("node_network_mtu_bytes", &enp5s0), Some(1500.0));
+        assert_eq!(
+            sink.value("node_network_carrier_changes_total", &enp5s0),
+            Some(5.0)
+        );
+        assert_eq!(
+            sink.value("node_network_carrier_up_changes_total", &enp5s0),
+            Some(3.0)
+        );
+        assert_eq!(
+            sink.value("node_network_carrier_down_changes_total", &enp5s0),
+            Some(2.0)
+        );
+
+        // Only operstate up counts, not unknown
+        let lo = [("device", "lo")];
+        assert_eq!(sink.value("node_network_up", &lo), Some(0.0));
+        assert_eq!(sink.value("node_network_mtu_bytes", &lo), Some(65536.0));
+
+        // bonding_masters is not an interface
+        let up = sink.family("node_network_up").unwrap();
+        assert_eq!(up.samples.len(), 3);
+    }
+
+    #[test]
+    fn missing_attributes() {
+        let sink = collect(&sysfs(), &DeviceFilterConfig::default()).unwrap();
+        // docker0 is down and has no carrier, speed or duplex
+        let docker0 = [("device", "docker0")];
+        assert_eq!(
+            sink.value(
+                "node_network_info",
+                &[
+                    ("device", "docker0"),
+                    ("operstate", "down"),
+                    ("address", "02:42:7b:5d:91:0a"),
+                    ("duplex", ""),
+                ]
+            ),
+            Some(1.0)
+        );
+        assert_eq!(sink.value("node_network_up", &docker0), Some(0.0));
+        assert_eq!(sink.value("node_network_carrier", &docker0), None);
+        assert_eq!(sink.value("node_network_speed_bytes", &docker0), None);
+        assert_eq!(sink.value("node_network_mtu_bytes", &docker0), Some(1500.0));
+        let carrier = sink.family("node_network_carrier").unwrap();
+        assert_eq!(carrier.samples.len(), 2);
+        let speed = sink.family("node_network_speed_bytes").unwrap();
+        assert_eq!(speed.samples.len(), 1);
+    }
+
+    #[test]
+    fn malformed_attributes() {
+        let sysfs = TempTree::new("net-class-malformed");
+        sysfs
+            .write("class/net/eth0/operstate", "up\n")
+            .write("class/net/eth0/carrier", "yes\n")
+            // Unknown speed
+            .write("class/net/eth0/speed", "-1\n")
+            .write("class/net/eth0/mtu", "\n")
+            .write("class/net/eth0/carrier_changes", "7\n");
+
+        let sink = collect(sysfs.path(), &DeviceFilterConfig::default()).unwrap();
+        let labels = [("device", "eth0")];
+        assert_eq!(sink.value("node_network_up", &labels), Some(1.0));
+        assert!(sink.family("node_network_carrier").is_none());
+        assert!(sink.family("node_network_speed_bytes").is_none());
+        assert!(sink.family("node_network_mtu_bytes").is_none());
+        assert_eq!(
+            sink.value("node_network_carrier_changes_total", &labels),
+            Some(7.0)
+        );
+    }
+
+    #[test]
+    fn device_filter() {
+        let config = DeviceFilterConfig {
+            device_include: Some("^enp".to_string()),
+            ..Default::default()
+        };
+        let sink = collect(&sysfs(), &config).unwrap();
+        let up = sink.family("node_network_up").unwrap();
+        assert_eq!(up.samples.len(), 1);
+        assert_eq!(
+            up.samples[0].labels,
+            [("device".to_string(), "enp5s0".to_string())]
+        );
+    }
+
+    #[test]
+    fn missing_sysfs() {
+        let result = collect(&sysfs().join("missing"), &DeviceFilterConfig::default());
+        assert!(matches!(result, Err(CollectorError::Unavailable(_))));
+    }
+}
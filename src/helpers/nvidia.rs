@@ -1,101 +1,155 @@
 use std::process::Command;
 use std::str;
 
+use crate::collector::{Collector, CollectorError};
+use crate::metrics::MetricSink;
 use quick_xml::de::from_str;
 use serde::Deserialize;
 
-pub fn get_nvidia_metrics() -> String {
-    match Command::new("nvidia-smi").arg("-q").arg("-x").output() {
-        Ok(output) => match str::from_utf8(&output.stdout) {
-            Ok(out) => {
-                let log: NvidiaSmiLog = from_str(out).unwrap();
-                let gpu = log.gpu;
+pub struct Nvidia;
 
-                let mut result = String::new();
-                result.push_str(&format!(
-                    "nvidia_temp {}",
-                    get_first_word(&gpu.temperature.gpu_temp)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_power_draw {}",
-                    get_first_word(&gpu.power_readings.power_draw)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_graphics_clock {}",
-                    get_first_word(&gpu.clocks.graphics_clock)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_sm_clock {}",
-                    get_first_word(&gpu.clocks.sm_clock)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_mem_clock {}",
-                    get_first_word(&gpu.clocks.mem_clock)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_video_clock {}",
-                    get_first_word(&gpu.clocks.video_clock)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_fan_speed {}",
-                    get_first_word(&gpu.fan_speed)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_fb_memory_total {}",
-                    get_first_word(&gpu.fb_memory_usage.total)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_fb_memory_free {}",
-                    get_first_word(&gpu.fb_memory_usage.free)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_fb_memory_used {}",
-                    get_first_word(&gpu.fb_memory_usage.used)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_bar1_memory_total {}",
-                    get_first_word(&gpu.bar1_memory_usage.total)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_bar1_memory_free {}",
-                    get_first_word(&gpu.bar1_memory_usage.free)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_bar1_memory_used {}",
-                    get_first_word(&gpu.bar1_memory_usage.used)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_utilization_gpu {}",
-                    get_first_word(&gpu.utilization.gpu_util)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_utilization_mem {}",
-                    get_first_word(&gpu.utilization.memory_util)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_utilization_enc {}",
-                    get_first_word(&gpu.utilization.encoder_util)
-                ));
-                result.push_str(&format!(
-                    "\nnvidia_utilization_dec {}\n",
-                    get_first_word(&gpu.utilization.decoder_util)
-                ));
-                result
-            }
-            Err(e) => {
-                eprintln!("error parsing nvidia-smi stdout {e}");
-                panic!()
-            }
-        },
-        Err(e) => {
-            eprintln!("error running nvidia-smi {e}");
-            panic!()
-        }
+impl Collector for Nvidia {
+    fn name(&self) -> &'static str {
+        "nvidia"
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        get_nvidia_metrics(sink)
+    }
+}
+
+fn get_nvidia_metrics(sink: &mut MetricSink) -> Result<(), CollectorError> {
+    let output = Command::new("nvidia-smi")
+        .arg("-q")
+        .arg("-x")
+        .output()
+        .map_err(|e| CollectorError::Unavailable(format!("error running nvidia-smi: {e}")))?;
+    if !output.status.success() {
+        return Err(CollectorError::Unavailable(format!(
+            "nvidia-smi exited with {}",
+            output.status
+        )));
+    }
+    let out = str::from_utf8(&output.stdout)
+        .map_err(|e| CollectorError::Parse(format!("error parsing nvidia-smi stdout: {e}")))?;
+    let log: NvidiaSmiLog = from_str(out)
+        .map_err(|e| CollectorError::Parse(format!("error parsing nvidia-smi xml: {e}")))?;
+    let gpu = log.gpu;
+
+    push_first_word(
+        sink,
+        "nvidia_temp",
+        "GPU temperature in Celsius",
+        &gpu.temperature.gpu_temp,
+    );
+    push_first_word(
+        sink,
+        "nvidia_power_draw",
+        "GPU power draw in watts",
+        &gpu.power_readings.power_draw,
+    );
+    push_first_word(
+        sink,
+        "nvidia_graphics_clock",
+        "Graphics clock in MHz",
+        &gpu.clocks.graphics_clock,
+    );
+    push_first_word(
+        sink,
+        "nvidia_sm_clock",
+        "SM clock in MHz",
+        &gpu.clocks.sm_clock,
+    );
+    push_first_word(
+        sink,
+        "nvidia_mem_clock",
+        "Memory clock in MHz",
+        &gpu.clocks.mem_clock,
+    );
+    push_first_word(
+        sink,
+        "nvidia_video_clock",
+        "Video clock in MHz",
+        &gpu.clocks.video_clock,
+    );
+    push_first_word(
+        sink,
+        "nvidia_fan_speed",
+        "Fan speed in percent",
+        &gpu.fan_speed,
+    );
+    push_first_word(
+        sink,
+        "nvidia_fb_memory_total",
+        "Total framebuffer memory in MiB",
+        &gpu.fb_memory_usage.total,
+    );
+    push_first_word(
+        sink,
+        "nvidia_fb_memory_free",
+        "Free framebuffer memory in MiB",
+        &gpu.fb_memory_usage.free,
+    );
+    push_first_word(
+        sink,
+        "nvidia_fb_memory_used",
+        "Used framebuffer memory in MiB",
+        &gpu.fb_memory_usage.used,
+    );
+    push_first_word(
+        sink,
+        "nvidia_bar1_memory_total",
+        "Total BAR1 memory in MiB",
+        &gpu.bar1_memory_usage.total,
+    );
+    push_first_word(
+        sink,
+        "nvidia_bar1_memory_free",
+        "Free BAR1 memory in MiB",
+        &gpu.bar1_memory_usage.free,
+    );
+    push_first_word(
+        sink,
+        "nvidia_bar1_memory_used",
+        "Used BAR1 memory in MiB",
+        &gpu.bar1_memory_usage.used,
+    );
+    push_first_word(
+        sink,
+        "nvidia_utilization_gpu",
+        "GPU utilization in percent",
+        &gpu.utilization.gpu_util,
+    );
+    push_first_word(
+        sink,
+        "nvidia_utilization_mem",
+        "Memory utilization in percent",
+        &gpu.utilization.memory_util,
+    );
+    push_first_word(
+        sink,
+        "nvidia_utilization_enc",
+        "Encoder utilization in percent",
+        &gpu.utilization.encoder_util,
+    );
+    push_first_word(
+        sink,
+        "nvidia_utilization_dec",
+        "Decoder utilization in percent",
+        &gpu.utilization.decoder_util,
+    );
+    Ok(())
+}
+
+/// Values look like "45 C" or "N/A", the latter are skipped
+fn push_first_word(sink: &mut MetricSink, name: &str, help: &str, s: &str) {
+    if let Ok(value) = get_first_word(s).parse::<f64>() {
+        sink.gauge(name, help, value);
     }
 }
 
 fn get_first_word(s: &str) -> &str {
-    s.split_whitespace().next().unwrap()
+    s.split_whitespace().next().unwrap_or_default()
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -1,23 +1,68 @@
+use crate::collector::{Collector, CollectorError};
+use crate::config::NzxtAioConfig;
+use crate::metrics::MetricSink;
 use hidapi::HidApi;
 
-const KRAKEN_X52_VID: u16 = 0x1e71;
-const KRAKEN_X52_PID: u16 = 0x170e;
+pub struct NzxtAio {
+    vendor_id: u16,
+    product_id: u16,
+}
+
+impl NzxtAio {
+    pub fn new(config: &NzxtAioConfig) -> NzxtAio {
+        NzxtAio {
+            vendor_id: config.vendor_id,
+            product_id: config.product_id,
+        }
+    }
+}
+
+impl Collector for NzxtAio {
+    fn name(&self) -> &'static str {
+        "nzxt_aio"
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        get_aio_metrics(self.vendor_id, self.product_id, sink)
+    }
+}
 
-pub fn get_aio_metrics() -> String {
-    let api = HidApi::new().expect("could not initialize hidapi");
-    let dev = api
-        .open(KRAKEN_X52_VID, KRAKEN_X52_PID)
-        .expect("could not connect to Kraken X52");
+fn get_aio_metrics(
+    vendor_id: u16,
+    product_id: u16,
+    sink: &mut MetricSink,
+) -> Result<(), CollectorError> {
+    let api = HidApi::new()
+        .map_err(|e| CollectorError::Unavailable(format!("could not initialize hidapi: {e}")))?;
+    let dev = api.open(vendor_id, product_id).map_err(|e| {
+        CollectorError::Unavailable(format!("could not connect to Kraken X52: {e}"))
+    })?;
     dev.set_blocking_mode(true)
-        .expect("could not set to blocking mode");
+        .map_err(|e| CollectorError::Unavailable(format!("could not set to blocking mode: {e}")))?;
 
     // Approach copied from here
     // https://github.com/liquidctl/liquidctl/blob/678ac64451da80cf335d7848ddfdfbbf9adaa92d/liquidctl/driver/kraken2.py#L140-L142
     let mut buf = [0u8; 64];
-    dev.read(&mut buf[..])
-        .expect("Could not read from Kraken USB devices");
+    dev.read(&mut buf[..]).map_err(|e| {
+        CollectorError::Unavailable(format!("could not read from Kraken USB device: {e}"))
+    })?;
     let temp = buf[1] as f64 + buf[2] as f64 / 10.0;
     let fan_speed = (buf[3] as u64) << 8 | (buf[4] as u64);
     let pump_speed = (buf[5] as u64) << 8 | (buf[6] as u64);
-    format!("aio_liquid_temp {temp}\naio_fan_speed {fan_speed}\naio_pump_speed {pump_speed}\n")
+    sink.gauge(
+        "aio_liquid_temp",
+        "Kraken X52 liquid temperature in Celsius",
+        temp,
+    );
+    sink.gauge(
+        "aio_fan_speed",
+        "Kraken X52 fan speed in RPM",
+        fan_speed as f64,
+    );
+    sink.gauge(
+        "aio_pump_speed",
+        "Kraken X52 pump speed in RPM",
+        pump_speed as f64,
+    );
+    Ok(())
 }
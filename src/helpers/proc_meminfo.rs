@@ -1,85 +1,208 @@
-use std::collections::HashMap;
+use crate::collector::{Collector, CollectorError};
+use crate::config::Compat;
+use crate::metrics::MetricSink;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
-use std::sync::LazyLock;
-
-static FIELD_MAP: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(|| {
-    let mut map = HashMap::new();
-    map.insert("MemTotal:", "procmeminfo_mem_total_bytes");
-    map.insert("MemFree:", "procmeminfo_mem_free_bytes");
-    map.insert("MemAvailable:", "procmeminfo_mem_available_bytes");
-    map.insert("Buffers:", "procmeminfo_buffers_bytes");
-    map.insert("Cached:", "procmeminfo_cached_bytes");
-    map.insert("SwapCached:", "procmeminfo_swap_cached_bytes");
-    map.insert("Active:", "procmeminfo_active_bytes");
-    map.insert("Inactive:", "procmeminfo_inactive_bytes");
-    map.insert("Active(anon):", "procmeminfo_active_anon_bytes");
-    map.insert("Inactive(anon):", "procmeminfo_inactive_anon_bytes");
-    map.insert("Active(file):", "procmeminfo_active_file_bytes");
-    map.insert("Inactive(file):", "procmeminfo_inactive_file_bytes");
-    map.insert("Unevictable:", "procmeminfo_unevictable_bytes");
-    map.insert("Mlocked:", "procmeminfo_mlocked_bytes");
-    map.insert("SwapTotal:", "procmeminfo_swap_total_bytes");
-    map.insert("SwapFree:", "procmeminfo_swap_free_bytes");
-    map.insert("Dirty:", "procmeminfo_swap_dirty_bytes");
-    map.insert("Writeback:", "procmeminfo_writeback_bytes");
-    map.insert("AnonPages:", "procmeminfo_anon_pages_bytes");
-    map.insert("Mapped:", "procmeminfo_mapped_bytes");
-    map.insert("Shmem:", "procmeminfo_shmem_bytes");
-    map.insert("KReclaimable:", "procmeminfo_kreclaimable_bytes");
-    map.insert("Slab:", "procmeminfo_slab_bytes");
-    map.insert("SReclaimable:", "procmeminfo_sreclaiamble_bytes");
-    map.insert("SUnreclaim:", "procmeminfo_sunreclaim_bytes");
-    map.insert("KernelStack:", "procmeminfo_kernel_stack_bytes");
-    map.insert("PageTables:", "procmeminfo_page_tables_bytes");
-    map.insert("NFS_Unstable:", "procmeminfo_nfs_unstable_bytes");
-    map.insert("Bounce:", "procmeminfo_bounce_bytes");
-    map.insert("WritebackTmp:", "procmeminfo_writeback_tmp_bytes");
-    map.insert("CommitLimit:", "procmeminfo_commit_limit_bytes");
-    map.insert("Committed_AS:", "procmeminfo_commited_as_bytes");
-    map.insert("VmallocTotal:", "procmeminfo_vmalloc_total_bytes");
-    map.insert("VmallocUsed:", "procmeminfo_vmalloc_used_bytes");
-    map.insert("VmallocChunk:", "procmeminfo_vmalloc_chunk_bytes");
-    map.insert("Percpu:", "procmeminfo_percpu_bytes");
-    map.insert("HardwareCorrupted:", "procmeminfo_hardware_corrupted_bytes");
-    map.insert("AnonHugePages:", "procmeminfo_anon_huge_pages_bytes");
-    map.insert("ShmemHugePages:", "procmeminfo_shmem_huge_pages_bytes");
-    map.insert("ShmemPmdMapped:", "procmeminfo_shmem_pmd_mapped_bytes");
-    map.insert("FileHugePages:", "procmeminfo_file_huge_pages_bytes");
-    map.insert("FilePmdMapped:", "procmeminfo_file_pmd_mapped_bytes");
-    map.insert("CmaTotal:", "procmeminfo_cma_total_bytes");
-    map.insert("CmaFree:", "procmeminfo_cma_free_bytes");
-    map.insert("HugePages_Total:", "procmeminfo_huge_pages_total_bytes");
-    map.insert("HugePages_Free:", "procmeminfo_huge_pages_free_bytes");
-    map.insert("HugePages_Rsvd:", "procmeminfo_huge_pages_rsvd_bytes");
-    map.insert("HugePages_Surp:", "procmeminfo_huge_pages_surp_bytes");
-    map.insert("Hugetlb:", "procmeminfo_hugetlb_bytes");
-    map.insert("DirectMap4k:", "procmeminfo_directmap4k_bytes");
-    map.insert("DirectMap2M:", "procmeminfo_directmap2m_bytes");
-    map.insert("DirectMap1G:", "procmeminfo_directmap1g_bytes");
-    map
-});
-
-pub fn get_proc_memifo() -> String {
-    let file = File::open("/proc/meminfo").expect("cannot open /proc/meminfo");
-    let lines = io::BufReader::new(file).lines();
+pub struct ProcMeminfo {
+    path: PathBuf,
+    compat: Compat,
+}
 
-    let mut result = String::new();
+impl ProcMeminfo {
+    pub fn new(procfs: &Path, compat: Compat) -> ProcMeminfo {
+        ProcMeminfo {
+            path: procfs.join("meminfo"),
+            compat,
+        }
+    }
+}
 
-    for line in lines.map_while(Result::ok) {
-        let mut iter = line.split_ascii_whitespace();
-        let first = iter.next().expect("first value expected");
-        let second = iter.next().expect("second value expected");
-        if let Some(line_ok) = iter.next() {
-            assert!(line_ok == "kB")
+impl Collector for ProcMeminfo {
+    fn name(&self) -> &'static str {
+        "proc_meminfo"
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        get_proc_memifo(&self.path, self.compat, sink)
+    }
+}
+
+/// Metric name of a meminfo field, e.g. procmeminfo_active_anon_bytes for Active(anon)
+/// and procmeminfo_huge_pages_total_pages for the page counts without unit, since
+/// a _total suffix is reserved for counters
+fn metric_name(field: &str, has_unit: bool) -> String {
+    let mut name = String::from("procmeminfo_");
+    let mut prev_lower = false;
+    for c in field.chars() {
+        match c {
+            '(' => name.push('_'),
+            ')' => {}
+            c if c.is_ascii_uppercase() => {
+                if prev_lower {
+                    name.push('_');
+                }
+                name.push(c.to_ascii_lowercase());
+            }
+            c => name.push(c),
         }
+        prev_lower = c.is_ascii_lowercase();
+    }
+    name.push_str(if has_unit { "_bytes" } else { "_pages" });
+    name
+}
+
+/// node_exporter name of a meminfo field, e.g. node_memory_Active_anon_bytes for
+/// Active(anon) and node_memory_HugePages_Total for the page counts without unit
+fn node_exporter_name(field: &str, has_unit: bool) -> String {
+    let field = field.replace('(', "_").replace(')', "");
+    if has_unit {
+        format!("node_memory_{field}_bytes")
+    } else {
+        format!("node_memory_{field}")
+    }
+}
+
+/// Exports every line, the kB values in bytes and the HugePages_* page counts as is
+fn get_proc_memifo(
+    path: &Path,
+    compat: Compat,
+    sink: &mut MetricSink,
+) -> Result<(), CollectorError> {
+    let file = File::open(path)?;
+    let lines = io::BufReader::new(file).lines();
 
-        if FIELD_MAP.contains_key(first) {
-            let kbytes: u64 = second.parse().unwrap();
-            result.push_str(&format!("{} {}\n", FIELD_MAP[first], kbytes * 1024));
+    for line in lines {
+        let line = line?;
+        let mut iter = line.split_ascii_whitespace();
+        let (Some(first), Some(second)) = (iter.next(), iter.next()) else {
+            return Err(CollectorError::Parse(format!("unexpected line {line}")));
+        };
+        let has_unit = match iter.next() {
+            Some("kB") => true,
+            Some(_) => return Err(CollectorError::Parse(format!("unexpected unit in {line}"))),
+            None => false,
+        };
+        let field = first.trim_end_matches(':');
+
+        let value: u64 = second.parse()?;
+        let value = if has_unit { value * 1024 } else { value };
+        let name = match compat {
+            Compat::None => metric_name(field, has_unit),
+            Compat::NodeExporter => node_exporter_name(field, has_unit),
+        };
+        sink.gauge(
+            &name,
+            &format!("Memory information field {field}"),
+            value as f64,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, assert_parse_error, TempTree};
+
+    fn procfs() -> PathBuf {
+        testing::fixtures().join("proc")
+    }
+
+    fn collect(procfs: &Path, compat: Compat) -> Result<MetricSink, CollectorError> {
+        testing::collect(&mut ProcMeminfo::new(procfs, compat))
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(metric_name("MemTotal", true), "procmeminfo_mem_total_bytes");
+        assert_eq!(
+            metric_name("Active(anon)", true),
+            "procmeminfo_active_anon_bytes"
+        );
+        assert_eq!(
+            metric_name("HugePages_Total", false),
+            "procmeminfo_huge_pages_total_pages"
+        );
+        assert_eq!(
+            node_exporter_name("Active(anon)", true),
+            "node_memory_Active_anon_bytes"
+        );
+        assert_eq!(
+            node_exporter_name("HugePages_Total", false),
+            "node_memory_HugePages_Total"
+        );
+    }
+
+    #[test]
+    fn fixture_native() {
+        let sink = collect(&procfs(), Compat::None).unwrap();
+        assert_eq!(sink.families().len(), 54);
+        assert_eq!(
+            sink.value("procmeminfo_mem_total_bytes", &[]),
+            Some(6158152.0 * 1024.0)
+        );
+        assert_eq!(
+            sink.value("procmeminfo_inactive_file_bytes", &[]),
+            Some(2884584.0 * 1024.0)
+        );
+        // Page counts have no unit in meminfo
+        assert_eq!(
+            sink.value("procmeminfo_huge_pages_total_pages", &[]),
+            Some(0.0)
+        );
+        assert_eq!(
+            sink.value("procmeminfo_huge_pages_free_pages", &[]),
+            Some(0.0)
+        );
+        // Every field is a gauge
+        assert!(sink
+            .families()
+            .iter()
+            .all(|family| !family.name.ends_with("_total")));
+        assert_eq!(
+            sink.value("procmeminfo_hugepagesize_bytes", &[]),
+            Some(2048.0 * 1024.0)
+        );
+    }
+
+    #[test]
+    fn fixture_node_exporter() {
+        let sink = collect(&procfs(), Compat::NodeExporter).unwrap();
+        assert_eq!(
+            sink.value("node_memory_MemAvailable_bytes", &[]),
+            Some(5454808.0 * 1024.0)
+        );
+        assert_eq!(
+            sink.value("node_memory_Active_anon_bytes", &[]),
+            Some(24.0 * 1024.0)
+        );
+        assert_eq!(sink.value("node_memory_HugePages_Total", &[]), Some(0.0));
+        assert_eq!(
+            sink.value("node_memory_VmallocTotal_bytes", &[]),
+            Some(34359738367.0 * 1024.0)
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let procfs = TempTree::new("meminfo-malformed");
+        for (case, meminfo) in [
+            ("value", "MemTotal:\n"),
+            ("unit", "MemTotal:        6158152 MB\n"),
+            ("number", "MemTotal:        lots kB\n"),
+            ("blank", "MemTotal:        6158152 kB\n\n"),
+        ] {
+            procfs.write("meminfo", meminfo);
+            assert_parse_error(case, collect(procfs.path(), Compat::None));
         }
     }
-    result
+
+    #[test]
+    fn missing_file() {
+        let result = collect(&procfs().join("missing"), Compat::None);
+        assert!(matches!(result, Err(CollectorError::Io(_))));
+    }
 }
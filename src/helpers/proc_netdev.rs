@@ -1,53 +1,243 @@
+use crate::collector::{Collector, CollectorError};
+use crate::config::{Compat, DeviceFilterConfig};
+use crate::helpers::device_filter::DeviceFilter;
+use crate::metrics::MetricSink;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::sync::LazyLock;
-
-static IFACE_FIELD_MAP: LazyLock<&'static [&'static str]> = LazyLock::new(|| {
-    &[
-        "rx",
-        "rx_packets",
-        "rx_errs",
-        "rx_drop",
-        "rx_fifo",
-        "rx_frame",
-        "rx_compressed",
-        "rx_multicast",
-        "tx",
-        "tx_packets",
-        "tx_errs",
-        "tx_drop",
-        "tx_fifo",
-        "tx_colls",
-        "tx_carrier",
-        "tx_compressed",
-    ]
-});
-
-pub fn get_proc_netdev() -> String {
-    let file = File::open("/proc/net/dev").expect("cannot open /proc/net/dev");
-    let lines = io::BufReader::new(file).lines();
-
-    let mut result = String::new();
-
-    for line in lines.map_while(Result::ok) {
-        let mut iter = line.split_ascii_whitespace();
-
-        let first_word = iter.next().unwrap();
-
-        if let Some(iface_name) = first_word.strip_suffix(":") {
-            let stuff: Vec<&str> = iter.collect();
-            if stuff.len() == 16 {
-                for (idx, item) in stuff.iter().enumerate() {
-                    result.push_str(&format!(
-                        "procnetdev_{}_bytes{{label=\"{}\"}} {}\n",
-                        IFACE_FIELD_MAP[idx], iface_name, item
-                    ));
-                }
-            } else {
-                panic!("Unexpected line {line} with iface");
+use std::path::{Path, PathBuf};
+
+/// Columns after the interface name, used as procnetdev_{name}_total
+const FIELDS: [&str; 16] = [
+    "rx_bytes",
+    "rx_packets",
+    "rx_errs",
+    "rx_drop",
+    "rx_fifo",
+    "rx_frame",
+    "rx_compressed",
+    "rx_multicast",
+    "tx_bytes",
+    "tx_packets",
+    "tx_errs",
+    "tx_drop",
+    "tx_fifo",
+    "tx_colls",
+    "tx_carrier",
+    "tx_compressed",
+];
+
+/// node_exporter names of the same columns, used as node_network_{name}_total
+const NODE_EXPORTER_FIELDS: [&str; 16] = [
+    "receive_bytes",
+    "receive_packets",
+    "receive_errs",
+    "receive_drop",
+    "receive_fifo",
+    "receive_frame",
+    "receive_compressed",
+    "receive_multicast",
+    "transmit_bytes",
+    "transmit_packets",
+    "transmit_errs",
+    "transmit_drop",
+    "transmit_fifo",
+    "transmit_colls",
+    "transmit_carrier",
+    "transmit_compressed",
+];
+
+pub struct ProcNetdev {
+    path: PathBuf,
+    compat: Compat,
+    devices: DeviceFilter,
+}
+
+impl ProcNetdev {
+    pub fn new(procfs: &Path, compat: Compat, config: &DeviceFilterConfig) -> ProcNetdev {
+        ProcNetdev {
+            path: procfs.join("net/dev"),
+            compat,
+            devices: DeviceFilter::new(config),
+        }
+    }
+
+    fn get_proc_netdev(&self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        let file = File::open(&self.path)?;
+        let lines = io::BufReader::new(file).lines();
+
+        // The first two lines are headers
+        for line in lines.skip(2) {
+            let line = line?;
+            // Large counters can follow the colon without a space, e.g. eth0:123456789012
+            let Some((device, values)) = line.split_once(':') else {
+                return Err(CollectorError::Parse(format!("unexpected line {line}")));
+            };
+            let device = device.trim();
+            if self.devices.is_ignored(device) {
+                continue;
+            }
+
+            let values: Vec<&str> = values.split_ascii_whitespace().collect();
+            if values.len() != FIELDS.len() {
+                return Err(CollectorError::Parse(format!(
+                    "unexpected line {line} with {} values",
+                    values.len()
+                )));
+            }
+            for (idx, item) in values.iter().enumerate() {
+                let (prefix, field) = match self.compat {
+                    Compat::None => ("procnetdev", FIELDS[idx]),
+                    Compat::NodeExporter => ("node_network", NODE_EXPORTER_FIELDS[idx]),
+                };
+                sink.counter_with_labels(
+                    &format!("{prefix}_{field}_total"),
+                    &format!("Network device statistic {field}"),
+                    &[("device", device)],
+                    item.parse()?,
+                );
             }
         }
+        Ok(())
+    }
+}
+
+impl Collector for ProcNetdev {
+    fn name(&self) -> &'static str {
+        "proc_netdev"
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        self.get_proc_netdev(sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricType;
+    use crate::testing::{self, assert_parse_error, TempTree};
+
+    const HEADER: &str = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+";
+
+    fn procfs() -> PathBuf {
+        testing::fixtures().join("proc")
+    }
+
+    fn collect(
+        procfs: &Path,
+        compat: Compat,
+        config: &DeviceFilterConfig,
+    ) -> Result<MetricSink, CollectorError> {
+        testing::collect(&mut ProcNetdev::new(procfs, compat, config))
+    }
+
+    /// Collects the device lines `dev` below the headers
+    fn collect_dev(procfs: &TempTree, dev: &str) -> Result<MetricSink, CollectorError> {
+        procfs.write("net/dev", &format!("{HEADER}{dev}"));
+        collect(procfs.path(), Compat::None, &DeviceFilterConfig::default())
+    }
+
+    #[test]
+    fn fixture_native() {
+        let sink = collect(&procfs(), Compat::None, &DeviceFilterConfig::default()).unwrap();
+        assert_eq!(sink.families().len(), FIELDS.len());
+        let rx_bytes = sink.family("procnetdev_rx_bytes_total").unwrap();
+        assert_eq!(rx_bytes.metric_type, MetricType::Counter);
+        assert_eq!(rx_bytes.samples.len(), 4);
+        assert_eq!(
+            sink.value("procnetdev_rx_bytes_total", &[("device", "enp5s0")]),
+            Some(9584756227.0)
+        );
+        assert_eq!(
+            sink.value("procnetdev_rx_multicast_total", &[("device", "enp5s0")]),
+            Some(41235.0)
+        );
+        assert_eq!(
+            sink.value("procnetdev_tx_drop_total", &[("device", "docker0")]),
+            Some(5.0)
+        );
+    }
+
+    #[test]
+    fn fixture_node_exporter() {
+        let sink = collect(
+            &procfs(),
+            Compat::NodeExporter,
+            &DeviceFilterConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            sink.value("node_network_receive_packets_total", &[("device", "wlan0")]),
+            Some(9471.0)
+        );
+        assert_eq!(
+            sink.value("node_network_transmit_bytes_total", &[("device", "lo")]),
+            Some(52820283.0)
+        );
+    }
+
+    #[test]
+    fn device_filter() {
+        let config = DeviceFilterConfig {
+            device_exclude: Some("^(lo|docker)".to_string()),
+            ..Default::default()
+        };
+        let sink = collect(&procfs(), Compat::None, &config).unwrap();
+        let devices: Vec<&str> = sink
+            .family("procnetdev_rx_bytes_total")
+            .unwrap()
+            .samples
+            .iter()
+            .map(|sample| sample.labels[0].1.as_str())
+            .collect();
+        assert_eq!(devices, ["enp5s0", "wlan0"]);
+    }
+
+    #[test]
+    fn counter_after_colon() {
+        let procfs = TempTree::new("netdev-counter-after-colon");
+        let sink = collect_dev(
+            &procfs,
+            "  eth0:123456789012 7402146 0 112 0 0 0 41235 618843325 3390178 0 0 0 0 0 0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            sink.value("procnetdev_rx_bytes_total", &[("device", "eth0")]),
+            Some(123456789012.0)
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let procfs = TempTree::new("netdev-malformed");
+        for (case, dev) in [
+            (
+                "colon",
+                "    lo 52820283 7345 0 0 0 0 0 0 52820283 7345 0 0 0 0 0 0\n",
+            ),
+            (
+                "columns",
+                "    lo: 52820283 7345 0 0 0 0 0 0 52820283 7345 0 0 0 0 0\n",
+            ),
+            (
+                "number",
+                "    lo: 52820283 7345 0 0 0 0 0 0 52820283 7345 0 0 0 0 0 -\n",
+            ),
+        ] {
+            assert_parse_error(case, collect_dev(&procfs, dev));
+        }
+    }
+
+    #[test]
+    fn missing_file() {
+        let result = collect(
+            &procfs().join("missing"),
+            Compat::None,
+            &DeviceFilterConfig::default(),
+        );
+        assert!(matches!(result, Err(CollectorError::Io(_))));
     }
-    result
 }
@@ -1,60 +1,374 @@
+use crate::collector::{Collector, CollectorError};
+use crate::config::{Compat, ProcStatConfig};
+use crate::metrics::{MetricSink, MetricType};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
 
-static FIELD_MAP: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(|| {
+/// Metric name, node_exporter name, type and help by field
+type Field = (&'static str, &'static str, MetricType, &'static str);
+
+static FIELD_MAP: LazyLock<HashMap<&'static str, Field>> = LazyLock::new(|| {
     let mut map = HashMap::new();
-    map.insert("ctxt", "procstat_ctxt");
-    map.insert("btime", "procstat_btime_seconds");
-    map.insert("processes", "procstat_processes");
-    map.insert("procs_running", "procstat_procs_running");
-    map.insert("procs_blocked", "procstat_procs_blocked");
-    map.insert("procs_softirq", "procstat_procs_blocked");
+    map.insert(
+        "ctxt",
+        (
+            "procstat_ctxt",
+            "node_context_switches_total",
+            MetricType::Counter,
+            "Total number of context switches",
+        ),
+    );
+    map.insert(
+        "btime",
+        (
+            "procstat_btime_seconds",
+            "node_boot_time_seconds",
+            MetricType::Gauge,
+            "Boot time in seconds since the epoch",
+        ),
+    );
+    map.insert(
+        "processes",
+        (
+            "procstat_processes",
+            "node_forks_total",
+            MetricType::Counter,
+            "Total number of forks since boot",
+        ),
+    );
+    map.insert(
+        "procs_running",
+        (
+            "procstat_procs_running",
+            "node_procs_running",
+            MetricType::Gauge,
+            "Number of processes in runnable state",
+        ),
+    );
+    map.insert(
+        "procs_blocked",
+        (
+            "procstat_procs_blocked",
+            "node_procs_blocked",
+            MetricType::Gauge,
+            "Number of processes blocked waiting for I/O",
+        ),
+    );
     map
 });
 
-static CPU_FIELDS_MAP: LazyLock<&'static [&'static str]> = LazyLock::new(|| {
-    &[
-        "user",
-        "nice",
-        "system",
-        "idle",
-        "iowait",
-        "irq",
-        "softirq",
-        "steal",
-        "guest",
-        "guestnice",
-    ]
-});
+/// Columns of the cpu lines, in USER_HZ
+const CPU_FIELDS: [&str; 10] = [
+    "user",
+    "nice",
+    "system",
+    "idle",
+    "iowait",
+    "irq",
+    "softirq",
+    "steal",
+    "guest",
+    "guestnice",
+];
+
+/// Columns of the softirq line after the total, see softirq_to_name in kernel/softirq.c
+const SOFTIRQ_TYPES: [&str; 10] = [
+    "hi", "timer", "net_tx", "net_rx", "block", "irq_poll", "tasklet", "sched", "hrtimer", "rcu",
+];
+
+pub struct ProcStat {
+    path: PathBuf,
+    /// USER_HZ, the unit of the cpu lines
+    clock_ticks: f64,
+    compat: Compat,
+    legacy_names: bool,
+}
+
+impl ProcStat {
+    pub fn new(procfs: &Path, compat: Compat, config: &ProcStatConfig) -> ProcStat {
+        // USER_HZ is 100 on almost every architecture
+        let clock_ticks = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+            ticks if ticks > 0 => ticks as f64,
+            _ => 100.0,
+        };
+        ProcStat {
+            path: procfs.join("stat"),
+            clock_ticks,
+            compat,
+            // node_exporter has no equivalent of the old names
+            legacy_names: config.legacy_names && compat == Compat::None,
+        }
+    }
+}
+
+impl Collector for ProcStat {
+    fn name(&self) -> &'static str {
+        "proc_stat"
+    }
+
+    fn collect(&mut self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        self.get_proc_stat(sink)
+    }
+}
+
+impl ProcStat {
+    fn get_proc_stat(&self, sink: &mut MetricSink) -> Result<(), CollectorError> {
+        let file = File::open(&self.path)?;
+        let lines = io::BufReader::new(file).lines();
 
-pub fn get_proc_stat() -> String {
-    let file = File::open("/proc/stat").expect("cannot open /proc/stat");
-    let lines = io::BufReader::new(file).lines();
-
-    let mut result = String::new();
-
-    for line in lines.map_while(Result::ok) {
-        let mut iter = line.split_ascii_whitespace();
-        let field_id = &iter.next().unwrap();
-        if line.starts_with("cpu") {
-            for (idx, item) in iter.enumerate() {
-                if idx < 10 {
-                    result.push_str(&format!(
-                        "procstat_{}_{}_hz {}\n",
-                        field_id, CPU_FIELDS_MAP[idx], item
-                    ));
+        for line in lines {
+            let line = line?;
+            let mut iter = line.split_ascii_whitespace();
+            let Some(field_id) = iter.next() else {
+                continue;
+            };
+            if field_id.starts_with("cpu") {
+                let values = iter
+                    .map(|item| item.parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>()?;
+                if self.legacy_names {
+                    self.legacy_cpu(field_id, &values, sink);
+                } else {
+                    self.cpu(field_id, &values, sink);
                 }
+            } else if field_id == "intr" {
+                // The total is followed by the counts of every numbered interrupt
+                let Some(total) = iter.next() else {
+                    return Err(CollectorError::Parse(format!("unexpected line {line}")));
+                };
+                sink.counter(
+                    self.name("procstat_intr_total", "node_intr_total"),
+                    "Total number of interrupts serviced",
+                    total.parse()?,
+                );
+            } else if field_id == "softirq" {
+                let Some(total) = iter.next() else {
+                    return Err(CollectorError::Parse(format!("unexpected line {line}")));
+                };
+                // node_exporter only has the breakdown, as node_softirqs_total{vector}
+                if self.compat == Compat::None {
+                    sink.counter(
+                        "procstat_softirq_total",
+                        "Total number of softirqs serviced",
+                        total.parse()?,
+                    );
+                }
+                for (softirq_type, item) in SOFTIRQ_TYPES.iter().zip(iter) {
+                    let (name, label) = match self.compat {
+                        Compat::None => ("procstat_softirqs_by_type_total", "type"),
+                        Compat::NodeExporter => ("node_softirqs_total", "vector"),
+                    };
+                    sink.counter_with_labels(
+                        name,
+                        "Number of softirqs serviced by type",
+                        &[(label, softirq_type)],
+                        item.parse()?,
+                    );
+                }
+            } else if FIELD_MAP.contains_key(field_id) {
+                let (name, node_exporter_name, metric_type, help) = FIELD_MAP[field_id];
+                let Some(value) = iter.next() else {
+                    return Err(CollectorError::Parse(format!("unexpected line {line}")));
+                };
+                sink.sample(
+                    self.name(name, node_exporter_name),
+                    help,
+                    metric_type,
+                    &[],
+                    value.parse()?,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&self, name: &'static str, node_exporter_name: &'static str) -> &'static str {
+        match self.compat {
+            Compat::None => name,
+            Compat::NodeExporter => node_exporter_name,
+        }
+    }
+
+    /// node_cpu_seconds_total{cpu,mode} for every CPU, the aggregated cpu line
+    /// is skipped since it is the sum of the others
+    fn cpu(&self, field_id: &str, values: &[f64], sink: &mut MetricSink) {
+        let Some(cpu) = field_id.strip_prefix("cpu").filter(|cpu| !cpu.is_empty()) else {
+            return;
+        };
+        for (mode, value) in CPU_FIELDS.iter().zip(values) {
+            let seconds = value / self.clock_ticks;
+            // guest time is already included in user and nice
+            match *mode {
+                "guest" | "guestnice" => sink.counter_with_labels(
+                    "node_cpu_guest_seconds_total",
+                    "Seconds the CPUs spent in guests (VMs) for each mode",
+                    &[
+                        ("cpu", cpu),
+                        ("mode", if *mode == "guest" { "user" } else { "nice" }),
+                    ],
+                    seconds,
+                ),
+                _ => sink.counter_with_labels(
+                    "node_cpu_seconds_total",
+                    "Seconds the CPUs spent in each mode",
+                    &[("cpu", cpu), ("mode", mode)],
+                    seconds,
+                ),
             }
-        } else if FIELD_MAP.contains_key(field_id) {
-            result.push_str(&format!(
-                "{} {}\n",
-                FIELD_MAP[field_id],
-                iter.next().unwrap()
-            ));
         }
     }
-    result
+
+    /// The names used before node_cpu_seconds_total, e.g. procstat_cpu3_user_hz
+    fn legacy_cpu(&self, field_id: &str, values: &[f64], sink: &mut MetricSink) {
+        for (mode, value) in CPU_FIELDS.iter().zip(values) {
+            sink.counter(
+                &format!("procstat_{field_id}_{mode}_hz"),
+                &format!("Time spent by {field_id} in {mode} mode in USER_HZ"),
+                *value,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, assert_parse_error, TempTree};
+
+    fn procfs() -> PathBuf {
+        testing::fixtures().join("proc")
+    }
+
+    fn collect(
+        procfs: &Path,
+        compat: Compat,
+        legacy_names: bool,
+    ) -> Result<MetricSink, CollectorError> {
+        let mut stat = ProcStat::new(procfs, compat, &ProcStatConfig { legacy_names });
+        // Independent of the USER_HZ of the machine running the tests
+        stat.clock_ticks = 100.0;
+        testing::collect(&mut stat)
+    }
+
+    fn collect_stat(procfs: &TempTree, stat: &str) -> Result<MetricSink, CollectorError> {
+        procfs.write("stat", stat);
+        collect(procfs.path(), Compat::None, false)
+    }
+
+    #[test]
+    fn fixture_native() {
+        let sink = collect(&procfs(), Compat::None, false).unwrap();
+        let cpu = sink.family("node_cpu_seconds_total").unwrap();
+        // The aggregated cpu line is left out, guest and guestnice have their own family
+        assert_eq!(cpu.samples.len(), 4 * 8);
+        assert_eq!(
+            sink.value("node_cpu_seconds_total", &[("cpu", "0"), ("mode", "user")]),
+            Some(564871.0 / 100.0)
+        );
+        assert_eq!(
+            sink.value("node_cpu_seconds_total", &[("cpu", "3"), ("mode", "idle")]),
+            Some(151370.25)
+        );
+        assert_eq!(
+            sink.value(
+                "node_cpu_guest_seconds_total",
+                &[("cpu", "1"), ("mode", "nice")]
+            ),
+            Some(0.0)
+        );
+        assert_eq!(sink.value("procstat_intr_total", &[]), Some(119835542.0));
+        assert_eq!(sink.value("procstat_ctxt", &[]), Some(241585287.0));
+        assert_eq!(
+            sink.value("procstat_btime_seconds", &[]),
+            Some(1732400000.0)
+        );
+        assert_eq!(
+            sink.family("procstat_btime_seconds").unwrap().metric_type,
+            MetricType::Gauge
+        );
+        assert_eq!(sink.value("procstat_processes", &[]), Some(392103.0));
+        assert_eq!(sink.value("procstat_procs_running", &[]), Some(2.0));
+        assert_eq!(sink.value("procstat_softirq_total", &[]), Some(52719830.0));
+        let by_type = sink.family("procstat_softirqs_by_type_total").unwrap();
+        assert_eq!(by_type.samples.len(), SOFTIRQ_TYPES.len());
+        assert_eq!(
+            sink.value("procstat_softirqs_by_type_total", &[("type", "net_rx")]),
+            Some(1293437.0)
+        );
+        assert_eq!(
+            sink.value("procstat_softirqs_by_type_total", &[("type", "rcu")]),
+            Some(13863996.0)
+        );
+    }
+
+    #[test]
+    fn fixture_node_exporter() {
+        let sink = collect(&procfs(), Compat::NodeExporter, true).unwrap();
+        // legacy_names has no effect in compat mode
+        assert!(sink.family("procstat_cpu0_user_hz").is_none());
+        assert!(sink.family("procstat_softirq_total").is_none());
+        assert_eq!(
+            sink.value(
+                "node_cpu_seconds_total",
+                &[("cpu", "2"), ("mode", "softirq")]
+            ),
+            Some(2874.0 / 100.0)
+        );
+        assert_eq!(sink.value("node_intr_total", &[]), Some(119835542.0));
+        assert_eq!(
+            sink.value("node_context_switches_total", &[]),
+            Some(241585287.0)
+        );
+        assert_eq!(
+            sink.value("node_boot_time_seconds", &[]),
+            Some(1732400000.0)
+        );
+        assert_eq!(sink.value("node_forks_total", &[]), Some(392103.0));
+        assert_eq!(sink.value("node_procs_blocked", &[]), Some(0.0));
+        assert_eq!(
+            sink.value("node_softirqs_total", &[("vector", "timer")]),
+            Some(17282914.0)
+        );
+    }
+
+    #[test]
+    fn fixture_legacy_names() {
+        let sink = collect(&procfs(), Compat::None, true).unwrap();
+        assert!(sink.family("node_cpu_seconds_total").is_none());
+        assert_eq!(sink.value("procstat_cpu_user_hz", &[]), Some(2255345.0));
+        assert_eq!(sink.value("procstat_cpu1_system_hz", &[]), Some(135908.0));
+    }
+
+    #[test]
+    fn unknown_and_blank_lines() {
+        let procfs = TempTree::new("stat-unknown-lines");
+        let sink = collect_stat(&procfs, "\npage 5741 1808\nctxt 12\n").unwrap();
+        assert_eq!(sink.families().len(), 1);
+        assert_eq!(sink.value("procstat_ctxt", &[]), Some(12.0));
+    }
+
+    #[test]
+    fn malformed() {
+        let procfs = TempTree::new("stat-malformed");
+        for (case, stat) in [
+            ("cpu", "cpu0 564871 412 - 15127343 5234 0 12893 0 0 0\n"),
+            ("intr", "intr\n"),
+            ("softirq", "softirq\n"),
+            ("softirq_type", "softirq 52719830 11 x\n"),
+            ("ctxt", "ctxt\n"),
+            ("btime", "btime yesterday\n"),
+        ] {
+            assert_parse_error(case, collect_stat(&procfs, stat));
+        }
+    }
+
+    #[test]
+    fn missing_file() {
+        let result = collect(&procfs().join("missing"), Compat::None, false);
+        assert!(matches!(result, Err(CollectorError::Io(_))));
+    }
 }
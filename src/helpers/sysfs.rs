@@ -0,0 +1,11 @@
+// Helpers for the collectors reading sysfs attributes
+use std::fs;
+use std::path::Path;
+
+/// Content of a single value attribute without the trailing newline, None if it
+/// cannot be read, e.g. sensors of sleeping devices fail with EIO
+pub fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+}
@@ -0,0 +1,298 @@
+// Minimal HTTP/1.1 support, one request per connection
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+// Upper bound for the request line and headers, the body is never read
+const MAX_HEAD_SIZE: usize = 8192;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    Io(io::Error),
+    Malformed(&'static str),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::Io(e) => write!(f, "could not read request: {e}"),
+            RequestError::Malformed(reason) => write!(f, "malformed request: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> Self {
+        RequestError::Io(e)
+    }
+}
+
+impl Request {
+    /// Reads the request line and headers from `stream`
+    pub fn read(stream: &mut impl Read) -> Result<Request, RequestError> {
+        let mut head = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let bytes_read = stream.read(&mut buffer)?;
+            if bytes_read == 0 {
+                return Err(RequestError::Malformed(
+                    "connection closed before end of headers",
+                ));
+            }
+            head.extend_from_slice(&buffer[..bytes_read]);
+            if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+                head.truncate(end);
+                break;
+            }
+            if head.len() > MAX_HEAD_SIZE {
+                return Err(RequestError::Malformed("headers too large"));
+            }
+        }
+
+        let head = String::from_utf8(head).map_err(|_| RequestError::Malformed("not utf-8"))?;
+        let mut lines = head.split("\r\n");
+
+        let request_line = lines.next().unwrap_or_default();
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(RequestError::Malformed("invalid request line"));
+        };
+        if method.is_empty() || !target.starts_with('/') || !version.starts_with("HTTP/1.") {
+            return Err(RequestError::Malformed("invalid request line"));
+        }
+
+        let mut headers = Vec::new();
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(RequestError::Malformed("invalid header"));
+            };
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+
+        // The query string is not used by any endpoint
+        let path = target.split('?').next().unwrap_or_default();
+
+        Ok(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers,
+        })
+    }
+
+    /// Returns the value of the header `name`, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", body)
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Response {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Writes the response, the body is skipped for HEAD requests
+    pub fn write_to(&self, stream: &mut impl Write, include_body: bool) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        head.push_str("Connection: close\r\n\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        if include_body {
+            stream.write_all(&self.body)?;
+        }
+        stream.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns one chunk per read call, like a socket receiving several packets
+    struct Chunks<'a>(Vec<&'a [u8]>);
+
+    impl Read for Chunks<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                self.0.insert(0, &chunk[n..]);
+            }
+            Ok(n)
+        }
+    }
+
+    fn malformed(result: Result<Request, RequestError>) -> &'static str {
+        match result {
+            Err(RequestError::Malformed(reason)) => reason,
+            Err(e) => panic!("unexpected error {e}"),
+            Ok(request) => panic!("unexpected request {} {}", request.method, request.path),
+        }
+    }
+
+    #[test]
+    fn request_split_across_reads() {
+        let mut stream = Chunks(vec![
+            b"GET /met",
+            b"rics HTTP/1.1\r\nHost: localhost\r",
+            b"\nAccept: text/plain\r\n\r",
+            b"\n",
+        ]);
+        let request = Request::read(&mut stream).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/metrics");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("accept"), Some("text/plain"));
+    }
+
+    #[test]
+    fn query_string_is_stripped() {
+        let mut stream: &[u8] = b"GET /metrics?collect[]=hwmon HTTP/1.1\r\n\r\n";
+        let request = Request::read(&mut stream).unwrap();
+        assert_eq!(request.path, "/metrics");
+    }
+
+    #[test]
+    fn header_lookup_ignores_case() {
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\nACCEPT:  application/openmetrics-text \r\n\r\n";
+        let request = Request::read(&mut stream).unwrap();
+        assert_eq!(
+            request.header("accept"),
+            Some("application/openmetrics-text")
+        );
+        assert_eq!(
+            request.header("Accept"),
+            Some("application/openmetrics-text")
+        );
+        assert_eq!(request.header("authorization"), None);
+    }
+
+    #[test]
+    fn head_too_large() {
+        let mut head = b"GET / HTTP/1.1\r\nX-Padding: ".to_vec();
+        head.resize(MAX_HEAD_SIZE + 1024, b'a');
+        head.extend_from_slice(b"\r\n\r\n");
+        assert_eq!(
+            malformed(Request::read(&mut head.as_slice())),
+            "headers too large"
+        );
+    }
+
+    #[test]
+    fn invalid_request_line() {
+        for head in [
+            &b"GET /metrics\r\n\r\n"[..],
+            b"GET metrics HTTP/1.1\r\n\r\n",
+            b"GET /metrics HTTP/2\r\n\r\n",
+            b"GET /metrics HTTP/1.1 extra\r\n\r\n",
+            b" /metrics HTTP/1.1\r\n\r\n",
+        ] {
+            assert_eq!(
+                malformed(Request::read(&mut &head[..])),
+                "invalid request line"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\nno colon\r\n\r\n";
+        assert_eq!(malformed(Request::read(&mut stream)), "invalid header");
+    }
+
+    #[test]
+    fn head_not_utf8() {
+        let mut stream: &[u8] = b"GET /\xff HTTP/1.1\r\n\r\n";
+        assert_eq!(malformed(Request::read(&mut stream)), "not utf-8");
+    }
+
+    #[test]
+    fn connection_closed_early() {
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n";
+        assert_eq!(
+            malformed(Request::read(&mut stream)),
+            "connection closed before end of headers"
+        );
+    }
+
+    #[test]
+    fn response_with_and_without_body() {
+        let response =
+            Response::text(405, "Method Not Allowed\n").with_header("Allow", "GET, HEAD");
+
+        let mut out = Vec::new();
+        response.write_to(&mut out, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTTP/1.1 405 Method Not Allowed\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Allow: GET, HEAD\r\n\
+             Content-Length: 19\r\n\
+             Connection: close\r\n\r\n\
+             Method Not Allowed\n"
+        );
+
+        // HEAD keeps the Content-Length of the body it leaves out
+        let mut out = Vec::new();
+        response.write_to(&mut out, false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.ends_with("Content-Length: 19\r\nConnection: close\r\n\r\n"),
+            "{out}"
+        );
+    }
+}
@@ -1,139 +1,403 @@
+#[cfg(feature = "nvidia")]
 extern crate quick_xml;
 extern crate serde;
+mod collector;
+mod config;
+mod encoding;
 mod helpers;
+mod http;
+mod metrics;
+mod server;
+#[cfg(test)]
+mod testing;
+mod thread_pool;
+mod web_config;
 
-use clap::{Parser, ValueEnum};
-use helpers::amd_radeontop;
-use std::io::prelude::*;
-use std::net::TcpListener;
-use std::net::TcpStream;
+use clap::Parser;
+use collector::{Exporter, Registry};
+use config::{Compat, Config, Overrides};
+use http::{Request, Response};
+use server::{Listener, Stream};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::path::PathBuf;
+use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use thread_pool::ThreadPool;
+use web_config::WebConfig;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Parser)]
 #[clap(author, version, about)]
 #[clap(about = "Prometheus exporter for my desktop metrics")]
 struct Cli {
-    /// Port where the HTTP server is listening
+    /// Port where the HTTP server is listening on 127.0.0.1, ignored if
+    /// --web.listen-address or --web.listen-unix is provided
     #[arg(default_value_t = 7878, short = 'p')]
-    port: u32,
+    port: u16,
+
+    /// Addresses to listen on, e.g. 0.0.0.0:7878 or [::]:7878. Can be repeated
+    /// or comma separated.
+    #[arg(
+        long = "web.listen-address",
+        value_name = "HOST:PORT",
+        value_delimiter = ','
+    )]
+    listen_addresses: Vec<String>,
+
+    /// Path of a Unix socket to listen on, instead of or in addition to TCP
+    #[arg(long = "web.listen-unix", value_name = "PATH")]
+    listen_unix: Option<PathBuf>,
+
+    /// Permissions of the Unix socket, in octal
+    #[arg(long = "web.unix-socket-mode", default_value = "660", value_parser = server::parse_mode)]
+    unix_socket_mode: u32,
 
-    /// List of enabled exporters (all are enabled if none provided)
+    /// Owner of the Unix socket as USER[:GROUP], names or numeric ids
+    #[arg(long = "web.unix-socket-owner", value_parser = server::parse_owner)]
+    unix_socket_owner: Option<(u32, Option<u32>)>,
+
+    /// List of enabled exporters, overrides the collectors from the config file
     #[arg(value_enum, short = 'x')]
     exporters: Vec<Exporter>,
+
+    /// Metric naming of the procfs collectors, overrides the config file
+    #[arg(long = "compat", value_enum)]
+    compat: Option<Compat>,
+
+    /// TOML file with the enabled collectors and their options
+    #[arg(long = "config.file", value_name = "PATH")]
+    config_file: Option<PathBuf>,
+
+    /// Address of the hddtemp daemon, overrides the config file
+    #[arg(long = "collector.hddtemp.address", value_name = "HOST:PORT")]
+    hddtemp_address: Option<String>,
+
+    /// Seconds between radeontop samples, overrides the config file
+    #[arg(long = "collector.amd_radeontop.interval", value_name = "SECONDS")]
+    radeontop_interval: Option<u32>,
+
+    /// Regex of the only network devices exported by proc_netdev, overrides the
+    /// config file
+    #[arg(long = "collector.netdev.device-include", value_name = "REGEX")]
+    netdev_device_include: Option<String>,
+
+    /// Regex of network devices left out by proc_netdev, overrides the config file
+    #[arg(long = "collector.netdev.device-exclude", value_name = "REGEX")]
+    netdev_device_exclude: Option<String>,
+
+    /// Regex of the only network devices exported by net_class, overrides the
+    /// config file
+    #[arg(long = "collector.netclass.device-include", value_name = "REGEX")]
+    netclass_device_include: Option<String>,
+
+    /// Regex of network devices left out by net_class, overrides the config file
+    #[arg(long = "collector.netclass.device-exclude", value_name = "REGEX")]
+    netclass_device_exclude: Option<String>,
+
+    /// Export CPU times with the old procstat_cpuN_MODE_hz names, ignored with
+    /// --compat=node_exporter
+    #[arg(long = "collector.proc_stat.legacy-names")]
+    proc_stat_legacy_names: bool,
+
+    /// procfs mountpoint, defaults to proc below --path.rootfs
+    #[arg(long = "path.procfs", value_name = "PATH")]
+    procfs: Option<PathBuf>,
+
+    /// sysfs mountpoint, defaults to sys below --path.rootfs
+    #[arg(long = "path.sysfs", value_name = "PATH")]
+    sysfs: Option<PathBuf>,
+
+    /// rootfs mountpoint, e.g. /host when the host is mounted into a container
+    #[arg(long = "path.rootfs", value_name = "PATH")]
+    rootfs: Option<PathBuf>,
+
+    /// YAML file with TLS and basic auth settings, see
+    /// https://prometheus.io/docs/prometheus/latest/configuration/https/
+    #[arg(long = "web.config.file", value_name = "PATH")]
+    web_config_file: Option<PathBuf>,
+
+    /// Enable POST /-/reload, which is unauthenticated without basic_auth_users
+    #[arg(long = "web.enable-lifecycle")]
+    enable_lifecycle: bool,
+
+    /// Number of connections which are served concurrently
+    #[arg(long = "web.workers", default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    workers: u16,
+
+    /// Seconds to wait for each collector before reporting it as failed
+    #[arg(long = "collector.timeout", default_value = "10", value_name = "SECONDS", value_parser = parse_seconds)]
+    collector_timeout: Duration,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-#[clap(rename_all = "snake_case")]
-enum Exporter {
-    Hddtemp,
-    LmSensors,
-    ProcMeminfo,
-    ProcNetdev,
-    ProcStat,
-    Nvidia,
-    NzxtAio, // No longer needed because these sensors are exposed in lm_sensors
-    AmdRadeontop,
+/// Positive number of seconds, Duration::from_secs_f64 panics on negative and NaN
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s.parse().map_err(|e| format!("invalid number {s}: {e}"))?;
+    if seconds.is_nan() || seconds <= 0.0 {
+        return Err(format!("{s} is not a positive number of seconds"));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("invalid duration {s}: {e}"))
+}
+
+struct State {
+    /// Replaced as a whole on reload, scrapes in progress keep using the old one
+    registry: RwLock<Arc<Registry>>,
+    collector_timeout: Duration,
+    web_config: WebConfig,
+    /// Whether POST /-/reload is served
+    enable_lifecycle: bool,
+    config_file: Option<PathBuf>,
+    overrides: Overrides,
+    /// Serializes reloads
+    reload_lock: Mutex<()>,
+}
+
+impl State {
+    fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.registry.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Re-reads the config file and swaps in the new collector set, the
+    /// current one stays active if the config is invalid
+    fn reload(&self) -> Result<(), String> {
+        let _guard = self.reload_lock.lock().unwrap_or_else(|e| e.into_inner());
+        println!("Reloading configuration");
+        let config = Config::load(self.config_file.as_deref(), &self.overrides)?;
+        let registry = Arc::new(self.registry().reload(&config));
+        *self.registry.write().unwrap_or_else(|e| e.into_inner()) = registry;
+        println!("Configuration reloaded");
+        Ok(())
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    let port: u32 = cli.port;
-    let mut exporters: Vec<Exporter> = cli.exporters;
-    if exporters.is_empty() {
-        // default exporters to enable
-        exporters = vec![
-            Exporter::Hddtemp,
-            Exporter::LmSensors,
-            Exporter::ProcMeminfo,
-            Exporter::ProcNetdev,
-            Exporter::ProcStat,
-            Exporter::AmdRadeontop,
-        ]
+    let mut listen_addresses = cli.listen_addresses;
+    if listen_addresses.is_empty() && cli.listen_unix.is_none() {
+        listen_addresses = vec![format!("127.0.0.1:{}", cli.port)];
     }
 
-    let mut lm_sensors = helpers::lm_sensors::get_lm_sensors();
+    let overrides = Overrides {
+        collectors: cli.exporters,
+        compat: cli.compat,
+        hddtemp_address: cli.hddtemp_address,
+        radeontop_interval: cli.radeontop_interval,
+        netdev_device_include: cli.netdev_device_include,
+        netdev_device_exclude: cli.netdev_device_exclude,
+        netclass_device_include: cli.netclass_device_include,
+        netclass_device_exclude: cli.netclass_device_exclude,
+        proc_stat_legacy_names: cli.proc_stat_legacy_names,
+        procfs: cli.procfs,
+        sysfs: cli.sysfs,
+        rootfs: cli.rootfs,
+    };
+    let config = Config::load(cli.config_file.as_deref(), &overrides)
+        .unwrap_or_else(|e| panic!("Could not load config: {e}"));
+
+    let web_config = match &cli.web_config_file {
+        Some(path) => WebConfig::load(path)
+            .unwrap_or_else(|e| panic!("Could not load web config {}: {e}", path.display())),
+        None => WebConfig::default(),
+    };
+
+    let mut registry = Registry::new(&config);
 
     let should_run = Arc::new(AtomicBool::new(true));
-    let r = should_run.clone();
 
-    let listener =
-        TcpListener::bind(format!("127.0.0.1:{port}")).expect("Could not bind to address");
+    let mut listeners: Vec<Listener> = listen_addresses
+        .iter()
+        .map(|addr| {
+            let listener = Listener::bind_tcp(addr)
+                .unwrap_or_else(|e| panic!("Could not bind to address {addr}: {e}"));
+            println!("Listening on {addr}");
+            listener
+        })
+        .collect();
+    if let Some(path) = &cli.listen_unix {
+        let listener = Listener::bind_unix(path, cli.unix_socket_mode, cli.unix_socket_owner)
+            .unwrap_or_else(|e| panic!("Could not bind to socket {}: {e}", path.display()));
+        println!("Listening on {}", path.display());
+        listeners.push(listener);
+    }
+
+    let shutdown_handles: Vec<_> = listeners
+        .iter()
+        .map(|listener| listener.shutdown_handle())
+        .collect();
 
-    let listener_addr = listener
-        .local_addr()
-        .expect("Could not get listener address");
+    // Signals received during initialization are handled once it is done
+    let mut signals =
+        Signals::new([SIGINT, SIGTERM, SIGHUP]).expect("Error setting signal handler");
 
-    ctrlc::set_handler(move || {
-        println!("Setting flag for termination from CTRLC handler");
-        r.store(false, Ordering::SeqCst);
-        // hackily wake up the listener thread
-        let _ = TcpStream::connect(listener_addr);
-    })
-    .expect("Error setting Ctrl-C handler");
+    registry.init();
 
-    if exporters.contains(&Exporter::LmSensors) {
-        lm_sensors.init();
+    let state = Arc::new(State {
+        registry: RwLock::new(Arc::new(registry)),
+        collector_timeout: cli.collector_timeout,
+        web_config,
+        enable_lifecycle: cli.enable_lifecycle,
+        config_file: cli.config_file,
+        overrides,
+        reload_lock: Mutex::new(()),
+    });
+
+    let r = should_run.clone();
+    // Weak so that the collectors are dropped at the end of main
+    let signal_state = Arc::downgrade(&state);
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                if let Some(state) = signal_state.upgrade() {
+                    if let Err(e) = state.reload() {
+                        println!("Could not reload configuration: {e}");
+                    }
+                }
+                continue;
+            }
+            if !r.load(Ordering::SeqCst) {
+                println!("Received signal {signal} again, exiting without cleanup");
+                process::exit(1);
+            }
+            println!("Received signal {signal}, shutting down");
+            r.store(false, Ordering::SeqCst);
+            for handle in shutdown_handles.iter() {
+                handle.shutdown();
+            }
+        }
+    });
+    let pool = ThreadPool::new(cli.workers.into());
+
+    // Every listener accepts on its own thread, connections are served by the pool
+    let (tx, rx) = mpsc::channel();
+    for listener in listeners {
+        let tx = tx.clone();
+        let should_run = should_run.clone();
+        let tls = state.web_config.tls.clone();
+        thread::spawn(move || server::accept_loop(listener, tls, tx, should_run));
     }
+    drop(tx);
 
-    if exporters.contains(&Exporter::AmdRadeontop) {
-        amd_radeontop::init();
+    for stream in rx {
+        let state = Arc::clone(&state);
+        pool.execute(move || {
+            let now = Instant::now();
+            handle_connection(stream, &state);
+            println!("Responded in {} ms", now.elapsed().as_millis());
+        });
     }
 
-    let mut handle_connection = |mut stream: TcpStream| {
-        let mut buffer = [0; 1024];
+    println!("Waiting for in-flight scrapes");
+    drop(pool);
+    // Dropping the collectors stops helper processes and releases libsensors
+    println!("Stopping collectors");
+    drop(state);
+    println!("Shutdown complete");
+}
 
-        let bytes_read = stream.read(&mut buffer).unwrap();
-        println!("Read bytes {bytes_read}");
+fn handle_connection(mut stream: Stream, state: &State) {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
 
-        let mut result = String::new();
-        if exporters.contains(&Exporter::NzxtAio) {
-            result.push_str(&helpers::nzxt_aio::get_aio_metrics());
-        }
-        if exporters.contains(&Exporter::LmSensors) {
-            result.push_str(&lm_sensors.get_lm_sensor_metrics());
-        }
-        if exporters.contains(&Exporter::Hddtemp) {
-            result.push_str(&helpers::hddtemp::get_hddtemp_metrics());
+    let (response, include_body) = match Request::read(&mut stream) {
+        Ok(request) => {
+            let response = route(&request, state);
+            println!("{} {} {}", request.method, request.path, response.status());
+            (response, request.method != "HEAD")
         }
-        if exporters.contains(&Exporter::Nvidia) {
-            result.push_str(&helpers::nvidia::get_nvidia_metrics());
-        }
-        if exporters.contains(&Exporter::ProcStat) {
-            result.push_str(&helpers::proc_stat::get_proc_stat());
-        }
-        if exporters.contains(&Exporter::ProcNetdev) {
-            result.push_str(&helpers::proc_netdev::get_proc_netdev());
+        Err(http::RequestError::Io(e)) => {
+            println!("Could not read request: {e}");
+            return;
         }
-        if exporters.contains(&Exporter::ProcMeminfo) {
-            result.push_str(&helpers::proc_meminfo::get_proc_memifo());
+        Err(e) => {
+            println!("{e}");
+            (Response::text(400, "Bad Request\n"), true)
         }
+    };
 
-        if exporters.contains(&Exporter::AmdRadeontop) {
-            result.push_str(&helpers::amd_radeontop::get_radeontop_stats());
-        }
+    if let Err(e) = response.write_to(&mut stream, include_body) {
+        println!("Could not write response: {e}");
+    }
+}
 
-        let response = format!(
-            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4;\r\nContent-Length: {}\r\n\r\n{}",
-            result.len(),
-            result
-        );
+fn route(request: &Request, state: &State) -> Response {
+    if !state.web_config.authorize(request.header("authorization")) {
+        return Response::text(401, "Unauthorized\n")
+            .with_header("WWW-Authenticate", "Basic realm=\"rust-node-exporter\"");
+    }
 
-        stream.write_all(response.as_bytes()).unwrap();
-        stream.flush().unwrap();
+    let allowed = match request.path.as_str() {
+        "/" | "/metrics" | "/-/healthy" | "/-/ready" => "GET, HEAD",
+        "/-/reload" => "POST",
+        _ => return Response::text(404, "Not Found\n"),
     };
+    if !allowed.split(", ").any(|method| method == request.method) {
+        return Response::text(405, "Method Not Allowed\n").with_header("Allow", allowed);
+    }
 
-    for stream in listener.incoming() {
-        if !should_run.load(Ordering::SeqCst) {
-            println!("CTRLC detected, terminating");
-            return;
+    match request.path.as_str() {
+        "/metrics" => {
+            let format = encoding::negotiate(request.header("accept"));
+            let scrape = state.registry().collect(state.collector_timeout);
+            for outcome in scrape.outcomes.iter() {
+                if let Err(e) = &outcome.result {
+                    println!(
+                        "Collector {} failed after {} ms: {e}",
+                        outcome.name,
+                        outcome.duration.as_millis()
+                    );
+                }
+            }
+            let result = format.encode(scrape.sink.families());
+            Response::new(200, format.content_type(), result)
         }
-        if let Ok(stream) = stream {
-            let now = Instant::now();
-            handle_connection(stream);
-            println!("Responded in {} ms", now.elapsed().as_millis());
+        "/-/healthy" => Response::text(200, "Healthy\n"),
+        // Collectors are initialized before the listener starts accepting requests
+        "/-/ready" => Response::text(200, "Ready\n"),
+        "/-/reload" if !state.enable_lifecycle => {
+            Response::text(403, "Lifecycle API is not enabled\n")
         }
+        "/-/reload" => match state.reload() {
+            Ok(()) => Response::text(200, "Reloaded\n"),
+            Err(e) => {
+                println!("Could not reload configuration: {e}");
+                Response::text(500, &format!("Could not reload configuration: {e}\n"))
+            }
+        },
+        _ => Response::new(
+            200,
+            "text/html; charset=utf-8",
+            landing_page(&state.registry()),
+        ),
     }
 }
+
+fn landing_page(registry: &Registry) -> String {
+    let collectors: Vec<String> = registry
+        .names()
+        .iter()
+        .map(|name| format!("<li>{name}</li>"))
+        .collect();
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head><title>rust-node-exporter</title></head>
+<body>
+<h1>rust-node-exporter {}</h1>
+<p><a href=\"/metrics\">Metrics</a></p>
+<h2>Enabled collectors</h2>
+<ul>
+{}
+</ul>
+</body>
+</html>
+",
+        env!("CARGO_PKG_VERSION"),
+        collectors.join("\n")
+    )
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl MetricType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+        }
+    }
+}
+
+pub struct Sample {
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+/// All samples sharing a metric name, together with their HELP and TYPE metadata
+pub struct MetricFamily {
+    pub name: String,
+    pub help: String,
+    pub metric_type: MetricType,
+    pub samples: Vec<Sample>,
+}
+
+/// Collects the metric families produced by the collectors during a single scrape
+#[derive(Default)]
+pub struct MetricSink {
+    families: Vec<MetricFamily>,
+    index: HashMap<String, usize>,
+}
+
+impl MetricSink {
+    // hwmon, net_class and lm_sensors only report labeled samples
+    #[cfg_attr(
+        not(any(
+            feature = "hidapi",
+            feature = "nvidia",
+            feature = "radeontop",
+            feature = "hddtemp",
+            feature = "procfs"
+        )),
+        allow(dead_code)
+    )]
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.sample(name, help, MetricType::Gauge, &[], value);
+    }
+
+    pub fn gauge_with_labels(
+        &mut self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.sample(name, help, MetricType::Gauge, labels, value);
+    }
+
+    #[cfg_attr(not(feature = "procfs"), allow(dead_code))]
+    pub fn counter(&mut self, name: &str, help: &str, value: f64) {
+        self.sample(name, help, MetricType::Counter, &[], value);
+    }
+
+    pub fn counter_with_labels(
+        &mut self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.sample(name, help, MetricType::Counter, labels, value);
+    }
+
+    /// Adds a sample to the family `name`, creating the family on first use.
+    /// HELP and TYPE of the first sample win if a family is reported twice.
+    pub fn sample(
+        &mut self,
+        name: &str,
+        help: &str,
+        metric_type: MetricType,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        let idx = match self.index.get(name) {
+            Some(idx) => *idx,
+            None => {
+                self.families.push(MetricFamily {
+                    name: name.to_string(),
+                    help: help.to_string(),
+                    metric_type,
+                    samples: Vec::new(),
+                });
+                self.index.insert(name.to_string(), self.families.len() - 1);
+                self.families.len() - 1
+            }
+        };
+
+        self.families[idx].samples.push(Sample {
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            value,
+        });
+    }
+
+    /// Appends the families of `other`, merging samples of families with the same name
+    pub fn merge(&mut self, other: MetricSink) {
+        for family in other.families {
+            match self.index.get(&family.name) {
+                Some(idx) => self.families[*idx].samples.extend(family.samples),
+                None => {
+                    self.index.insert(family.name.clone(), self.families.len());
+                    self.families.push(family);
+                }
+            }
+        }
+    }
+
+    pub fn families(&self) -> &[MetricFamily] {
+        &self.families
+    }
+
+    #[cfg(test)]
+    pub fn family(&self, name: &str) -> Option<&MetricFamily> {
+        self.index.get(name).map(|idx| &self.families[*idx])
+    }
+
+    /// Value of the sample of `name` with exactly these labels
+    #[cfg(test)]
+    pub fn value(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        self.family(name)?
+            .samples
+            .iter()
+            .find(|sample| {
+                sample.labels.len() == labels.len()
+                    && labels
+                        .iter()
+                        .all(|(k, v)| sample.labels.iter().any(|(sk, sv)| sk == k && sv == v))
+            })
+            .map(|sample| sample.value)
+    }
+}
@@ -0,0 +1,246 @@
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    /// The handshake happens on the first read, on the worker serving the connection
+    Tls(Box<StreamOwned<ServerConnection, Stream>>),
+}
+
+/// Wakes up a thread blocked in accept on a listener, which then sees an error
+pub struct ShutdownHandle(RawFd);
+
+impl Listener {
+    pub fn bind_tcp(addr: &str) -> io::Result<Listener> {
+        Ok(Listener::Tcp(TcpListener::bind(addr)?))
+    }
+
+    /// Binds a Unix socket at `path`, replacing a stale socket left by a previous run
+    pub fn bind_unix(
+        path: &Path,
+        mode: u32,
+        owner: Option<(u32, Option<u32>)>,
+    ) -> io::Result<Listener> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        if let Some((uid, gid)) = owner {
+            std::os::unix::fs::chown(path, Some(uid), gid)?;
+        }
+        Ok(Listener::Unix(listener, path.to_path_buf()))
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        match self {
+            Listener::Tcp(listener) => ShutdownHandle(listener.as_raw_fd()),
+            Listener::Unix(listener, _) => ShutdownHandle(listener.as_raw_fd()),
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Listener::Unix(listener, _) => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Must be called before the listener is dropped, i.e. while its accept
+    /// loop is still running
+    pub fn shutdown(&self) {
+        // On Linux shutdown of a listening socket makes a blocked accept fail
+        unsafe {
+            libc::shutdown(self.0, libc::SHUT_RDWR);
+        }
+    }
+}
+
+impl Stream {
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            Stream::Tls(stream) => stream.sock.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Accepts connections and hands them to `tx` until `should_run` is cleared.
+/// Connections are wrapped in TLS if `tls` is set.
+pub fn accept_loop(
+    listener: Listener,
+    tls: Option<Arc<ServerConfig>>,
+    tx: mpsc::Sender<Stream>,
+    should_run: Arc<AtomicBool>,
+) {
+    loop {
+        let stream = listener.accept();
+        if !should_run.load(Ordering::SeqCst) {
+            return;
+        }
+        let stream = match (stream, &tls) {
+            (Ok(stream), None) => stream,
+            (Ok(stream), Some(tls)) => match ServerConnection::new(Arc::clone(tls)) {
+                Ok(connection) => Stream::Tls(Box::new(StreamOwned::new(connection, stream))),
+                Err(e) => {
+                    println!("Could not create TLS connection: {e}");
+                    continue;
+                }
+            },
+            (Err(e), _) => {
+                println!("Could not accept connection: {e}");
+                continue;
+            }
+        };
+        if tx.send(stream).is_err() {
+            return;
+        }
+    }
+}
+
+/// Parses a socket mode such as 660 or 0o660
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.trim_start_matches("0o");
+    u32::from_str_radix(digits, 8).map_err(|e| format!("invalid octal mode {s}: {e}"))
+}
+
+/// Parses USER[:GROUP] where both can be names or numeric ids
+pub fn parse_owner(s: &str) -> Result<(u32, Option<u32>), String> {
+    let (user, group) = match s.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (s, None),
+    };
+    let uid = lookup_uid(user).ok_or(format!("unknown user {user}"))?;
+    let gid = match group {
+        Some(group) => Some(lookup_gid(group).ok_or(format!("unknown group {group}"))?),
+        None => None,
+    };
+    Ok((uid, gid))
+}
+
+/// Resolves through NSS, so LDAP and systemd-homed users are found too
+fn lookup_uid(name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    let name = CString::new(name).ok()?;
+    with_nss_buffer(|buf| {
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        (rc, (!result.is_null()).then_some(passwd.pw_uid))
+    })
+}
+
+fn lookup_gid(name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    let name = CString::new(name).ok()?;
+    with_nss_buffer(|buf| {
+        let mut group: libc::group = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let rc = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut group,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        (rc, (!result.is_null()).then_some(group.gr_gid))
+    })
+}
+
+/// Calls `lookup` with a growing buffer for the strings of the entry until it
+/// no longer fails with ERANGE
+fn with_nss_buffer<F>(mut lookup: F) -> Option<u32>
+where
+    F: FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<u32>),
+{
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        match lookup(&mut buf) {
+            (libc::ERANGE, _) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            (0, id) => return id,
+            _ => return None,
+        }
+    }
+}
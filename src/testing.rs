@@ -0,0 +1,69 @@
+// Fixture trees and scratch directories shared by the unit tests
+use crate::collector::{Collector, CollectorError};
+use crate::metrics::MetricSink;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// The captured procfs and sysfs trees below fixtures/
+pub fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+/// Directory below the system temp dir which is removed again on drop, also
+/// when an assertion fails
+pub struct TempTree {
+    root: PathBuf,
+}
+
+impl TempTree {
+    /// `name` has to be unique across the tests since they run in parallel
+    pub fn new(name: &str) -> TempTree {
+        let root =
+            std::env::temp_dir().join(format!("rust-node-exporter-{name}-{}", process::id()));
+        // Left over by a test which was killed
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        TempTree { root }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Writes `content` to `file` relative to the root, creating the parent directories
+    pub fn write(&self, file: &str, content: &str) -> &TempTree {
+        let path = self.root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        self
+    }
+
+    pub fn create_dir(&self, dir: &str) -> &TempTree {
+        fs::create_dir_all(self.root.join(dir)).unwrap();
+        self
+    }
+}
+
+impl Drop for TempTree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Runs a single scrape of `collector`
+pub fn collect(collector: &mut dyn Collector) -> Result<MetricSink, CollectorError> {
+    let mut sink = MetricSink::default();
+    collector.collect(&mut sink)?;
+    Ok(sink)
+}
+
+/// Fails unless parsing the input of `case` was rejected
+#[cfg_attr(not(feature = "procfs"), allow(dead_code))]
+pub fn assert_parse_error(case: &str, result: Result<MetricSink, CollectorError>) {
+    match result {
+        Err(CollectorError::Parse(_)) => {}
+        Err(e) => panic!("{case} failed with {e} instead of a parse error"),
+        Ok(_) => panic!("{case} was accepted"),
+    }
+}
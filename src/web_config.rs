@@ -0,0 +1,376 @@
+// Web configuration file in the format used by the Prometheus exporter toolkit
+// https://prometheus.io/docs/prometheus/latest/configuration/https/
+use base64::prelude::*;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Top-level keys of the exporter toolkit which are not supported, such as
+/// http_server_config, are ignored with a warning so that files written for
+/// node_exporter still load
+#[derive(Deserialize, Default)]
+struct WebConfigFile {
+    tls_server_config: Option<TlsServerConfig>,
+    #[serde(default)]
+    basic_auth_users: HashMap<String, String>,
+    #[serde(flatten)]
+    unsupported: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Deserialize)]
+struct TlsServerConfig {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    #[serde(default)]
+    client_auth_type: ClientAuthType,
+    client_ca_file: Option<PathBuf>,
+    /// Defaults to TLS12
+    min_version: Option<TlsVersion>,
+    max_version: Option<TlsVersion>,
+    /// Go names of the allowed TLS 1.2 suites, TLS 1.3 suites are not configurable
+    #[serde(default)]
+    cipher_suites: Vec<String>,
+    /// Defaults to true as in the exporter toolkit
+    prefer_server_cipher_suites: Option<bool>,
+    /// Go names of the key exchange groups in order of preference
+    #[serde(default)]
+    curve_preferences: Vec<String>,
+    /// Rejected since ignoring a key such as client_allowed_sans would accept
+    /// more clients than configured
+    #[serde(flatten)]
+    unsupported: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Go names of the TLS versions, rustls only implements 1.2 and 1.3
+#[derive(Deserialize, Clone, Copy, PartialEq, PartialOrd)]
+enum TlsVersion {
+    #[serde(rename = "TLS10")]
+    Tls10,
+    #[serde(rename = "TLS11")]
+    Tls11,
+    #[serde(rename = "TLS12")]
+    Tls12,
+    #[serde(rename = "TLS13")]
+    Tls13,
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+enum ClientAuthType {
+    #[default]
+    NoClientCert,
+    VerifyClientCertIfGiven,
+    RequireAndVerifyClientCert,
+}
+
+#[derive(Default)]
+pub struct WebConfig {
+    pub tls: Option<Arc<ServerConfig>>,
+    /// bcrypt hashes by user name
+    users: HashMap<String, String>,
+}
+
+impl WebConfig {
+    pub fn load(path: &Path) -> Result<WebConfig, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: WebConfigFile = serde_yaml::from_str(&content).map_err(|e| e.to_string())?;
+        for key in file.unsupported.keys() {
+            println!("Ignoring unsupported web config key {key}");
+        }
+
+        // Paths in the file are relative to the directory of the file
+        let base = path.parent().unwrap_or(Path::new("."));
+        let tls = match file.tls_server_config {
+            Some(tls) => Some(Arc::new(tls.server_config(base)?)),
+            None => None,
+        };
+
+        Ok(WebConfig {
+            tls,
+            users: file.basic_auth_users,
+        })
+    }
+
+    /// Checks the `Authorization` header, always passes if no users are configured
+    pub fn authorize(&self, authorization: Option<&str>) -> bool {
+        if self.users.is_empty() {
+            return true;
+        }
+
+        let Some((user, password)) = authorization
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| BASE64_STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                decoded
+                    .split_once(':')
+                    .map(|(user, password)| (user.to_string(), password.to_string()))
+            })
+        else {
+            return false;
+        };
+
+        match self.users.get(&user) {
+            Some(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            None => {
+                // Verify against some hash anyway so that the response time
+                // does not reveal whether the user exists
+                if let Some(hash) = self.users.values().next() {
+                    let _ = bcrypt::verify(password, hash);
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Maps the Go curve names to the names of the rustls key exchange groups
+fn kx_group_name(curve: &str) -> Option<&'static str> {
+    match curve {
+        "X25519" => Some("X25519"),
+        "CurveP256" => Some("secp256r1"),
+        "CurveP384" => Some("secp384r1"),
+        _ => None,
+    }
+}
+
+impl TlsServerConfig {
+    fn protocol_versions(&self) -> Result<Vec<&'static SupportedProtocolVersion>, String> {
+        let min = self.min_version.unwrap_or(TlsVersion::Tls12);
+        let max = self.max_version.unwrap_or(TlsVersion::Tls13);
+        let versions: Vec<_> = [
+            (TlsVersion::Tls12, &rustls::version::TLS12),
+            (TlsVersion::Tls13, &rustls::version::TLS13),
+        ]
+        .into_iter()
+        .filter(|(version, _)| min <= *version && *version <= max)
+        .map(|(_, supported)| supported)
+        .collect();
+        if versions.is_empty() {
+            // rustls has neither TLS 1.0 nor 1.1
+            return Err("min_version and max_version leave no supported TLS version".to_string());
+        }
+        Ok(versions)
+    }
+
+    /// The ring provider restricted to the configured cipher suites and curves
+    fn crypto_provider(&self) -> Result<CryptoProvider, String> {
+        let mut provider = rustls::crypto::ring::default_provider();
+        if !self.cipher_suites.is_empty() {
+            let mut suites: Vec<SupportedCipherSuite> = provider
+                .cipher_suites
+                .iter()
+                .filter(|suite| matches!(suite, SupportedCipherSuite::Tls13(_)))
+                .copied()
+                .collect();
+            for name in self.cipher_suites.iter() {
+                let suite = provider
+                    .cipher_suites
+                    .iter()
+                    .find(|suite| suite.suite().as_str() == Some(name.as_str()))
+                    .ok_or(format!("unsupported cipher suite {name}"))?;
+                suites.push(*suite);
+            }
+            provider.cipher_suites = suites;
+        }
+        if !self.curve_preferences.is_empty() {
+            let mut groups = Vec::new();
+            for curve in self.curve_preferences.iter() {
+                let group = kx_group_name(curve)
+                    .and_then(|name| {
+                        provider
+                            .kx_groups
+                            .iter()
+                            .find(|group| group.name().as_str() == Some(name))
+                    })
+                    .ok_or(format!("unsupported curve {curve}"))?;
+                groups.push(*group);
+            }
+            provider.kx_groups = groups;
+        }
+        Ok(provider)
+    }
+
+    fn server_config(&self, base: &Path) -> Result<ServerConfig, String> {
+        if let Some(key) = self.unsupported.keys().next() {
+            return Err(format!(
+                "unsupported web config key tls_server_config.{key}"
+            ));
+        }
+
+        let cert_file = base.join(&self.cert_file);
+        let certs = CertificateDer::pem_file_iter(&cert_file)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("could not read {}: {e}", cert_file.display()))?;
+        let key_file = base.join(&self.key_file);
+        let key = PrivateKeyDer::from_pem_file(&key_file)
+            .map_err(|e| format!("could not read {}: {e}", key_file.display()))?;
+
+        let provider = Arc::new(self.crypto_provider()?);
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&self.protocol_versions()?)
+            .map_err(|e| e.to_string())?;
+        let builder = if self.client_auth_type == ClientAuthType::NoClientCert {
+            builder.with_no_client_auth()
+        } else {
+            let Some(client_ca_file) = &self.client_ca_file else {
+                return Err("client_ca_file is required to verify client certificates".to_string());
+            };
+            let client_ca_file = base.join(client_ca_file);
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(&client_ca_file)
+                .map_err(|e| format!("could not read {}: {e}", client_ca_file.display()))?
+            {
+                let cert = cert.map_err(|e| e.to_string())?;
+                roots.add(cert).map_err(|e| e.to_string())?;
+            }
+
+            let mut verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            if self.client_auth_type == ClientAuthType::VerifyClientCertIfGiven {
+                verifier = verifier.allow_unauthenticated();
+            }
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+        };
+
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| e.to_string())?;
+        config.ignore_client_order = self.prefer_server_cipher_suites.unwrap_or(true);
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Request, Response};
+    use crate::testing::TempTree;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, ServerConnection, StreamOwned};
+    use std::io::prelude::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Writes a self-signed certificate for localhost and a web config using it
+    /// with the user alice:secret
+    fn write_config(
+        dir: &TempTree,
+        tls_extra: &str,
+        extra: &str,
+    ) -> (PathBuf, CertificateDer<'static>) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        let config = format!(
+            "tls_server_config:\n  cert_file: cert.pem\n  key_file: key.pem\n{tls_extra}{extra}basic_auth_users:\n  alice: '{hash}'\n"
+        );
+        dir.write("cert.pem", &certified.cert.pem())
+            .write("key.pem", &certified.signing_key.serialize_pem())
+            .write("web.yml", &config);
+        (dir.path().join("web.yml"), certified.cert.der().clone())
+    }
+
+    /// Serves a single request over TLS and returns the raw response
+    fn request(config: &WebConfig, cert: CertificateDer<'static>, authorization: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tls = config.tls.clone().unwrap();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let (tcp, _) = listener.accept().unwrap();
+                let mut stream = StreamOwned::new(ServerConnection::new(tls).unwrap(), tcp);
+                let request = Request::read(&mut stream).unwrap();
+                let response = if config.authorize(request.header("authorization")) {
+                    Response::text(200, "OK\n")
+                } else {
+                    Response::text(401, "Unauthorized\n")
+                };
+                response.write_to(&mut stream, true).unwrap();
+                stream.conn.send_close_notify();
+                stream.flush().unwrap();
+            });
+
+            let mut roots = RootCertStore::empty();
+            roots.add(cert).unwrap();
+            let client = ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+            let connection =
+                ClientConnection::new(Arc::new(client), ServerName::try_from("localhost").unwrap())
+                    .unwrap();
+            let mut stream = StreamOwned::new(connection, TcpStream::connect(addr).unwrap());
+            write!(
+                stream,
+                "GET /metrics HTTP/1.1\r\nHost: localhost\r\nAuthorization: {authorization}\r\n\r\n"
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", BASE64_STANDARD.encode(credentials))
+    }
+
+    #[test]
+    fn tls_with_basic_auth() {
+        let dir = TempTree::new("web-config-tls");
+        let (path, cert) = write_config(&dir, "", "");
+        let config = WebConfig::load(&path).unwrap();
+
+        let response = request(&config, cert.clone(), &basic("alice:secret"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        let response = request(&config, cert.clone(), &basic("alice:wrong"));
+        assert!(
+            response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "{response}"
+        );
+        let response = request(&config, cert, &basic("mallory:secret"));
+        assert!(
+            response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "{response}"
+        );
+    }
+
+    #[test]
+    fn exporter_toolkit_keys() {
+        let tls_extra = "  min_version: TLS13\n  \
+            cipher_suites: [TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256]\n  \
+            curve_preferences: [X25519, CurveP256]\n  \
+            prefer_server_cipher_suites: false\n";
+        let extra = "http_server_config:\n  http2: false\n";
+        let dir = TempTree::new("web-config-toolkit");
+        let (path, cert) = write_config(&dir, tls_extra, extra);
+        let config = WebConfig::load(&path).unwrap();
+
+        let response = request(&config, cert, &basic("alice:secret"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    }
+
+    #[test]
+    fn unsupported_tls_settings() {
+        let dir = TempTree::new("web-config-unsupported");
+        for (name, tls_extra) in [
+            ("version", "  max_version: TLS11\n"),
+            ("range", "  min_version: TLS13\n  max_version: TLS12\n"),
+            ("suite", "  cipher_suites: [TLS_RSA_WITH_RC4_128_SHA]\n"),
+            ("curve", "  curve_preferences: [CurveP521]\n"),
+            ("sans", "  client_allowed_sans: [prometheus]\n"),
+        ] {
+            let (path, _) = write_config(&dir, tls_extra, "");
+            assert!(WebConfig::load(&path).is_err(), "{name}");
+        }
+    }
+}